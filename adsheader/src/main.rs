@@ -1,36 +1,91 @@
-use std::{fs::File, io::Write, path::PathBuf};
+use std::{fs::File, io::Write, num::NonZeroU32, path::PathBuf};
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 
 #[derive(Parser)]
 #[clap(version)]
 struct Args {
     input: PathBuf,
-    channels: u32,
-    sample_rate: u32,
+    channels: NonZeroU32,
+    sample_rate: NonZeroU32,
+    /// The interleave in bytes, 0 for mono streams.
     interleave: u32,
-    format: u32,
+    format: Format,
+    /// The sample to loop back to, requires `--loop-end`.
+    #[clap(long, requires = "loop_end")]
+    loop_start: Option<u32>,
+    /// The sample to loop at, requires `--loop-start`.
+    #[clap(long, requires = "loop_start")]
+    loop_end: Option<u32>,
+    /// Pads the body with silence to a multiple of this many bytes, 0x800 if no value is given.
+    #[clap(short, long, num_args = 0..=1, default_missing_value = "2048")]
+    align: Option<u32>,
     #[clap(short, long)]
     output: Option<PathBuf>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// `PCM16_LE`
+    #[value(alias = "1")]
+    Pcm16le,
+    /// `SONY_4BIT_ADPCM`
+    #[value(alias = "16")]
+    Vag,
+}
+
+impl Format {
+    fn codec(self) -> u32 {
+        match self {
+            Self::Pcm16le => 0x01,
+            Self::Vag => 0x10,
+        }
+    }
+
+    /// The smallest amount of bytes the body of a channel can be split in.
+    fn block_size(self) -> u32 {
+        match self {
+            Self::Pcm16le => 2,
+            Self::Vag => 16,
+        }
+    }
+
+    fn samples(self, bytes: u32) -> u32 {
+        match self {
+            Self::Pcm16le => bytes / 2,
+            Self::Vag => bytes / 16 * 28,
+        }
+    }
+}
+
 fn main() {
     let args = Args::parse();
 
     let mut file = std::fs::read(&args.input).unwrap();
-    let file_len = file.len();
+    if let Some(align) = args.align {
+        if align != 0 {
+            let padded = file.len().next_multiple_of(align as usize);
+            file.resize(padded, 0);
+        }
+    }
+
+    if let Err(err) = validate(&args, file.len() as u32) {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
 
+    let file_len = file.len();
     file.splice(
         0..0,
         [
             [0x53, 0x53, 0x68, 0x64],
             [0x18, 0, 0, 0],
-            args.format.to_le_bytes(),
-            args.sample_rate.to_le_bytes(),
-            args.channels.to_le_bytes(),
+            args.format.codec().to_le_bytes(),
+            args.sample_rate.get().to_le_bytes(),
+            args.channels.get().to_le_bytes(),
             args.interleave.to_le_bytes(),
-            [0xff; 4],
-            [0xff; 4],
+            args.loop_start.unwrap_or(u32::MAX).to_le_bytes(),
+            args.loop_end.unwrap_or(u32::MAX).to_le_bytes(),
             [0x53, 0x53, 0x62, 0x64],
             (file_len as u32).to_le_bytes(),
         ]
@@ -38,6 +93,108 @@ fn main() {
         .flatten(),
     );
 
-    let mut output = File::create(args.input.with_extension("ads")).unwrap();
+    let mut output = File::create(
+        args.output
+            .unwrap_or_else(|| args.input.with_extension("ads")),
+    )
+    .unwrap();
     output.write_all(&file).unwrap();
 }
+
+/// Checks that the header fields agree with each other and with the body.
+fn validate(args: &Args, body_size: u32) -> Result<(), String> {
+    let channels = args.channels.get();
+    let block_size = args.format.block_size();
+
+    if channels == 1 && args.interleave != 0 {
+        return Err("The interleave must be 0 when there is only one channel".to_owned());
+    }
+    if channels > 1 && args.interleave == 0 {
+        return Err("The interleave must be greater than 0 with multiple channels".to_owned());
+    }
+    if !args.interleave.is_multiple_of(block_size) {
+        return Err(format!(
+            "The interleave must be a multiple of {block_size} bytes for {:?}",
+            args.format
+        ));
+    }
+    // The padding has to end on a whole row of interleave blocks, or the last one would be uneven
+    if let Some(align) = args.align.filter(|align| *align != 0) {
+        let row_size = u64::from(args.interleave.max(block_size)) * u64::from(channels);
+        if !u64::from(align).is_multiple_of(row_size) {
+            return Err(format!(
+                "The alignment must be a multiple of {row_size} bytes, the size of a block of \
+                 every channel"
+            ));
+        }
+    }
+    if !body_size.is_multiple_of(block_size * channels) {
+        return Err(format!(
+            "The body size {body_size} isn't a multiple of {} bytes",
+            block_size * channels
+        ));
+    }
+
+    if let (Some(start), Some(end)) = (args.loop_start, args.loop_end) {
+        let samples = args.format.samples(body_size / channels);
+        if start >= end {
+            return Err(format!(
+                "The loop start {start} must be before the loop end {end}"
+            ));
+        }
+        if end > samples {
+            return Err(format!(
+                "The loop end {end} is past the end of the stream at {samples}"
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn validation() {
+    let args = Args::parse_from(["adsheader", "in.bin", "2", "44100", "0", "vag"]);
+    assert!(validate(&args, 64).is_err());
+
+    let args = Args::parse_from(["adsheader", "in.bin", "2", "44100", "16", "16"]);
+    assert_eq!(args.format, Format::Vag);
+    assert!(validate(&args, 64).is_ok());
+    assert!(validate(&args, 48).is_err());
+
+    let args = Args::parse_from([
+        "adsheader",
+        "in.bin",
+        "1",
+        "22050",
+        "0",
+        "pcm16le",
+        "--loop-start",
+        "0",
+        "--loop-end",
+        "32",
+    ]);
+    assert!(validate(&args, 64).is_ok());
+    assert!(validate(&args, 62).is_err());
+
+    let args = Args::parse_from([
+        "adsheader",
+        "in.bin",
+        "2",
+        "44100",
+        "1024",
+        "vag",
+        "--align",
+    ]);
+    assert!(validate(&args, 0x800).is_ok());
+    let args = Args::parse_from([
+        "adsheader",
+        "in.bin",
+        "2",
+        "44100",
+        "2048",
+        "vag",
+        "--align",
+    ]);
+    assert!(validate(&args, 0x800).is_err());
+}
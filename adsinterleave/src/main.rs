@@ -0,0 +1,337 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use spuadpcm::{decode, interleaved};
+
+const ADS_MAGIC_NUMBER: [u8; 4] = [0x53, 0x53, 0x68, 0x64];
+const VAG_MAGIC_NUMBER: [u8; 4] = [0x56, 0x41, 0x47, 0x70];
+
+#[derive(Parser)]
+#[clap(version)]
+struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Splits a multichannel `ads` file into one file per channel.
+    Split {
+        input: PathBuf,
+        #[clap(short, long, default_value = "ads")]
+        format: Format,
+        /// The output directory
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Interleaves mono `ads` or `vag` files into one `ads` file.
+    Join {
+        #[clap(required = true)]
+        inputs: Vec<PathBuf>,
+        /// The interleave in bytes.
+        #[clap(short, long, default_value_t = 0x800)]
+        interleave: u32,
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Ads,
+    Vag,
+    Wav,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Codec {
+    Pcm16le,
+    Vag,
+}
+
+impl Codec {
+    fn from_number(number: u32) -> Option<Self> {
+        match number {
+            0x01 => Some(Self::Pcm16le),
+            0x10 => Some(Self::Vag),
+            _ => None,
+        }
+    }
+
+    fn number(self) -> u32 {
+        match self {
+            Self::Pcm16le => 0x01,
+            Self::Vag => 0x10,
+        }
+    }
+
+    /// The smallest amount of bytes the body of a channel can be split in.
+    fn block_size(self) -> u32 {
+        match self {
+            Self::Pcm16le => 2,
+            Self::Vag => 16,
+        }
+    }
+}
+
+/// A mono stream or a multichannel stream with its interleave.
+struct Stream {
+    codec: Codec,
+    sample_rate: u32,
+    channels: u32,
+    interleave: u32,
+    loop_start: u32,
+    loop_end: u32,
+    body: Vec<u8>,
+}
+
+impl Stream {
+    fn parse(path: &Path) -> Result<Self, String> {
+        let file = std::fs::read(path).unwrap();
+        match file.get(0..4) {
+            Some(magic) if magic == ADS_MAGIC_NUMBER && file.len() >= 0x28 => {
+                let codec = load_bytes(&file[8..]);
+                let body_size = load_bytes(&file[0x24..]) as usize;
+                if body_size + 0x28 != file.len() {
+                    eprintln!(
+                        "{}: body size is {body_size} bytes, but the file has {} bytes of body",
+                        path.to_string_lossy(),
+                        file.len() - 0x28
+                    );
+                }
+                Ok(Self {
+                    codec: Codec::from_number(codec)
+                        .ok_or_else(|| format!("Unsupported codec {codec:#x}"))?,
+                    sample_rate: load_bytes(&file[0x0C..]),
+                    channels: load_bytes(&file[0x10..]).max(1),
+                    interleave: load_bytes(&file[0x14..]),
+                    loop_start: load_bytes(&file[0x18..]),
+                    loop_end: load_bytes(&file[0x1C..]),
+                    body: file[0x28..(0x28 + body_size).min(file.len())].to_vec(),
+                })
+            }
+            Some(magic) if magic == VAG_MAGIC_NUMBER && file.len() >= 48 => {
+                let data_size =
+                    u32::from_be_bytes([file[12], file[13], file[14], file[15]]) as usize;
+                let header_size = if data_size + 64 == file.len() { 64 } else { 48 };
+                Ok(Self {
+                    codec: Codec::Vag,
+                    sample_rate: u32::from_be_bytes([file[16], file[17], file[18], file[19]]),
+                    channels: 1,
+                    interleave: 0,
+                    loop_start: u32::MAX,
+                    loop_end: u32::MAX,
+                    body: file[header_size..].to_vec(),
+                })
+            }
+            _ => Err(format!(
+                "{}: expected an ADS or VAG file",
+                path.to_string_lossy()
+            )),
+        }
+    }
+
+    fn ads_bytes(&self) -> Vec<u8> {
+        [
+            ADS_MAGIC_NUMBER,
+            [0x18, 0, 0, 0],
+            self.codec.number().to_le_bytes(),
+            self.sample_rate.to_le_bytes(),
+            self.channels.to_le_bytes(),
+            self.interleave.to_le_bytes(),
+            self.loop_start.to_le_bytes(),
+            self.loop_end.to_le_bytes(),
+            [0x53, 0x53, 0x62, 0x64],
+            (self.body.len() as u32).to_le_bytes(),
+        ]
+        .into_iter()
+        .flatten()
+        .chain(self.body.iter().copied())
+        .collect()
+    }
+
+    fn vag_bytes(&self) -> Vec<u8> {
+        [
+            VAG_MAGIC_NUMBER,
+            [0, 0, 0, 3],
+            [0; 4],
+            (self.body.len() as u32).to_be_bytes(),
+            self.sample_rate.to_be_bytes(),
+            [0; 4],
+            [0; 4],
+            [0; 4],
+            [0; 4],
+            [0; 4],
+            [0; 4],
+            [0; 4],
+        ]
+        .into_iter()
+        .flatten()
+        .chain(self.body.iter().copied())
+        .collect()
+    }
+
+    fn wav_bytes(&self) -> Vec<u8> {
+        let samples = match self.codec {
+            Codec::Pcm16le => self.body.clone(),
+            Codec::Vag => decode(&self.body)
+                .into_iter()
+                .flat_map(i16::to_le_bytes)
+                .collect(),
+        };
+        [
+            *b"RIFF",
+            (samples.len() as u32 + 36).to_le_bytes(),
+            *b"WAVE",
+            *b"fmt ",
+            16u32.to_le_bytes(),
+            [1, 0, 1, 0],
+            self.sample_rate.to_le_bytes(),
+            (self.sample_rate * 2).to_le_bytes(),
+            [2, 0, 16, 0],
+            *b"data",
+            (samples.len() as u32).to_le_bytes(),
+        ]
+        .into_iter()
+        .flatten()
+        .chain(samples)
+        .collect()
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let result = match args.command {
+        Command::Split {
+            input,
+            format,
+            output,
+        } => split(&input, format, output),
+        Command::Join {
+            inputs,
+            interleave,
+            output,
+        } => join(&inputs, interleave, output),
+    };
+    if let Err(err) = result {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+}
+
+fn split(input: &Path, format: Format, output: Option<PathBuf>) -> Result<(), String> {
+    let stream = Stream::parse(input)?;
+    if format == Format::Vag && stream.codec != Codec::Vag {
+        return Err("Only SONY_4BIT_ADPCM streams can be written as VAG files".to_owned());
+    }
+    if stream.channels > 1 && stream.interleave == 0 {
+        return Err("The interleave must be greater than 0 with multiple channels".to_owned());
+    }
+
+    let output = output.unwrap_or_else(|| input.parent().unwrap().to_owned());
+    let stem = input.file_stem().unwrap().to_string_lossy();
+    let channels = deinterleave(&stream.body, stream.channels, stream.interleave);
+    println!("Channels found: {}", channels.len());
+
+    for (i, body) in channels.into_iter().enumerate() {
+        let channel = Stream {
+            channels: 1,
+            interleave: 0,
+            body,
+            ..stream
+        };
+        let (bytes, extension) = match format {
+            Format::Ads => (channel.ads_bytes(), "ads"),
+            Format::Vag => (channel.vag_bytes(), "vag"),
+            Format::Wav => (channel.wav_bytes(), "wav"),
+        };
+        let mut out = File::create(output.join(format!("{stem}_ch{i}.{extension}"))).unwrap();
+        out.write_all(&bytes).unwrap();
+    }
+    Ok(())
+}
+
+fn join(inputs: &[PathBuf], interleave: u32, output: Option<PathBuf>) -> Result<(), String> {
+    let streams = inputs
+        .iter()
+        .map(|input| Stream::parse(input))
+        .collect::<Result<Vec<_>, _>>()?;
+    let first = &streams[0];
+    if let Some(stream) = streams.iter().find(|stream| stream.channels != 1) {
+        return Err(format!(
+            "Only mono streams can be joined, found {} channels",
+            stream.channels
+        ));
+    }
+    if streams
+        .iter()
+        .any(|stream| stream.codec != first.codec || stream.sample_rate != first.sample_rate)
+    {
+        return Err("Every stream must have the same codec and samplerate".to_owned());
+    }
+    if interleave == 0 || !interleave.is_multiple_of(first.codec.block_size()) {
+        return Err(format!(
+            "The interleave must be a non-zero multiple of {} bytes",
+            first.codec.block_size()
+        ));
+    }
+
+    let body = interleaved(
+        streams.iter().map(|stream| stream.body.as_slice()),
+        interleave as usize,
+    );
+    let joined = Stream {
+        codec: first.codec,
+        sample_rate: first.sample_rate,
+        channels: streams.len() as u32,
+        interleave,
+        loop_start: first.loop_start,
+        loop_end: first.loop_end,
+        body,
+    };
+    let mut out = File::create(output.unwrap_or_else(|| {
+        format!(
+            "{}_joined.ads",
+            inputs[0].with_extension("").to_string_lossy()
+        )
+        .into()
+    }))
+    .unwrap();
+    out.write_all(&joined.ads_bytes()).unwrap();
+    Ok(())
+}
+
+fn deinterleave(body: &[u8], channels: u32, interleave: u32) -> Vec<Vec<u8>> {
+    if channels == 1 {
+        return vec![body.to_vec()];
+    }
+    let mut streams = vec![vec![]; channels as usize];
+    for (i, block) in body.chunks(interleave as usize).enumerate() {
+        streams[i % channels as usize].extend_from_slice(block);
+    }
+    streams
+}
+
+fn load_bytes(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[test]
+fn roundtrip() {
+    let left = vec![1; 48];
+    let right = vec![2; 16];
+    let body = interleaved([left.as_slice(), right.as_slice()].into_iter(), 32);
+    assert_eq!(body.len(), 128);
+    assert_eq!(body[32..48], [2; 16]);
+    assert_eq!(body[48..64], [0; 16]);
+
+    let channels = deinterleave(&body, 2, 32);
+    assert_eq!(channels[0][..48], left[..]);
+    assert_eq!(channels[0][48..], [0; 16]);
+    assert_eq!(channels[1][..16], right[..]);
+}
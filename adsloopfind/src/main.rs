@@ -1,65 +1,584 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use clap::Parser;
+use loopexport::{flag_loop, LoopFormat, LoopPoints};
 
 const MAGIC_NUMBER: [u8; 4] = [0x53, 0x53, 0x68, 0x64];
+const VAG_MAGIC_NUMBER: [u8; 4] = [0x56, 0x41, 0x47, 0x70];
 
 #[derive(Parser)]
 struct Args {
-    /// The `ads` file to find loops in
+    /// The `ads` or `vag` file to find loops in, or a directory of `ads` files
     ads_input: PathBuf,
+    /// Whether to also scan the subdirectories
+    #[clap(short, long)]
+    recursive: bool,
+    /// The loop file to write in directory mode, `loop.txt` inside the directory by default
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+    /// The formats to export the loops in, `txt` goes to the standard output for a single file
+    #[clap(short, long, value_delimiter = ',', default_value = "txt")]
+    format: Vec<LoopFormat>,
+    #[clap(flatten)]
+    search: PcmSearch,
+    /// Reads the input as a `loop.txt` file, such as a `_smploopinfo.txt` from `demus`, and checks
+    /// its loop points for clicks
+    #[clap(short, long, conflicts_with_all = ["recursive", "write"])]
+    check: bool,
+    /// Stores the loop points in the file itself, in the header for `ads` and in the flags for `vag`
+    #[clap(short, long)]
+    write: bool,
+    /// The loop start and end to store instead of the ones found, in samples
+    #[clap(long, num_args = 2, value_names = ["START", "END"], requires = "write")]
+    r#loop: Option<Vec<u32>>,
+}
+
+/// The options of the loop search for `PCM16_LE` streams.
+#[derive(clap::Args)]
+struct PcmSearch {
+    /// The first sample a PCM loop can start at
+    #[clap(long, default_value_t = 0)]
+    window_start: u32,
+    /// The last sample a PCM loop can start at
+    #[clap(long)]
+    window_end: Option<u32>,
+    /// How many PCM loop candidates to list
+    #[clap(long, default_value_t = 3)]
+    candidates: usize,
+    /// The confidence a PCM loop needs to be used, from 0 to 1
+    #[clap(long, default_value_t = 0.9)]
+    min_confidence: f64,
 }
 
 fn main() {
     let args = Args::parse();
-    let ads_file = std::fs::read(&args.ads_input).unwrap();
-
-    if let Some((lb, le)) = find_loops(&ads_file) {
-        print!(
-            "{lb} {le} {}\r\n",
-            args.ads_input
-                .with_extension("wav")
-                .file_name()
-                .unwrap()
-                .to_string_lossy()
-        );
+
+    if let Some([lb, le]) = args.r#loop.as_deref() {
+        if lb > le {
+            eprintln!("The loop start {lb} is after the loop end {le}");
+            std::process::exit(1);
+        }
+    }
+
+    if args.check {
+        check_loops(&args.ads_input);
+        return;
+    }
+
+    if args.ads_input.is_dir() {
+        if args.r#loop.is_some() {
+            eprintln!("Loop points can only be given for a single file");
+            std::process::exit(1);
+        }
+        let mut paths = vec![];
+        collect_ads(&args.ads_input, args.recursive, &mut paths);
+        paths.sort();
+
+        let mut loops = vec![];
+        for path in &paths {
+            let mut file = std::fs::read(path).unwrap();
+            match find_loops(&file, &args.search) {
+                Ok(Some((lb, le))) => {
+                    loops.push(loop_points(path, lb, le));
+                    if args.write {
+                        match write_loops(&mut file, lb, le) {
+                            Ok(()) => std::fs::write(path, &file).unwrap(),
+                            Err(err) => eprintln!("{}: {err}", path.to_string_lossy()),
+                        }
+                    }
+                }
+                Ok(None) => eprintln!("{}: no loop", path.to_string_lossy()),
+                Err(err) => eprintln!("{}: {err}", path.to_string_lossy()),
+            }
+        }
+        println!("Files with loops: {}/{}", loops.len(), paths.len());
+
+        for format in &args.format {
+            match (format, &args.output) {
+                (LoopFormat::Txt, Some(output)) => std::fs::write(
+                    output,
+                    loops.iter().map(LoopPoints::loop_txt).collect::<String>(),
+                )
+                .unwrap(),
+                _ => loopexport::export(&loops, *format, &args.ads_input, "loop").unwrap(),
+            }
+        }
+        return;
+    }
+
+    let mut ads_file = std::fs::read(&args.ads_input).unwrap();
+    let loops = match &args.r#loop {
+        Some(points) => Ok(Some((points[0], points[1]))),
+        None => find_loops(&ads_file, &args.search),
+    };
+    match loops {
+        Ok(Some((lb, le))) => {
+            let points = loop_points(&args.ads_input, lb, le);
+            for format in &args.format {
+                match format {
+                    LoopFormat::Txt => print!("{}", points.loop_txt()),
+                    _ => loopexport::export(
+                        std::slice::from_ref(&points),
+                        *format,
+                        args.ads_input.parent().unwrap(),
+                        points.stem(),
+                    )
+                    .unwrap(),
+                }
+            }
+            if args.write {
+                if let Err(err) = write_loops(&mut ads_file, lb, le) {
+                    eprintln!("{err}");
+                    std::process::exit(1);
+                }
+                std::fs::write(&args.ads_input, &ads_file).unwrap();
+            }
+        }
+        Ok(None) => {}
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
     }
 }
 
-fn find_loops(ads_file: &[u8]) -> Option<(u32, u32)> {
-    if ads_file[0..4] != MAGIC_NUMBER {
+/// Stores a loop in the file, with `le` being the last sample played.
+///
+/// ADS files get the loop start and the sample after the loop end in their header, while VAG files
+/// get the loop start, loop body and loop end flags on the frames holding those samples.
+fn write_loops(file: &mut [u8], lb: u32, le: u32) -> Result<(), String> {
+    if lb > le {
+        return Err(format!("The loop start {lb} is after the loop end {le}"));
+    }
+
+    if file.get(0..4) == Some(&MAGIC_NUMBER) {
+        file[0x18..0x1C].copy_from_slice(&lb.to_le_bytes());
+        file[0x1C..0x20].copy_from_slice(&(le + 1).to_le_bytes());
+        return Ok(());
+    }
+    if file.get(0..4) != Some(&VAG_MAGIC_NUMBER) || file.len() < 48 {
+        return Err("Loops can only be written to ADS and VAG files".to_owned());
+    }
+
+    let header_size = vag_header_size(file);
+    let frame_count = (file.len() - header_size) / 16;
+    let (start, end) = (lb as usize / 28, le as usize / 28);
+    if end >= frame_count {
+        return Err(format!(
+            "The loop end {le} is past the end of the stream at {}",
+            frame_count * 28
+        ));
+    }
+    if !lb.is_multiple_of(28) || !(le + 1).is_multiple_of(28) {
         eprintln!(
-            "Invalid magic number, expected {MAGIC_NUMBER:?}, found {:?}",
-            &ads_file[0..4]
+            "Loop points rounded to whole frames: {} {}",
+            start * 28,
+            (end + 1) * 28 - 1
         );
-        std::process::exit(1);
     }
 
-    let body_size = load_bytes(&ads_file[0x24..]);
+    for (i, frame) in file[header_size..].chunks_exact_mut(16).enumerate() {
+        frame[1] = match i {
+            i if i < start => 0,
+            i if i == start && i == end => 7,
+            i if i == start => 6,
+            i if i < end => 2,
+            i if i == end => 3,
+            _ => frame[1],
+        };
+    }
+    Ok(())
+}
+
+/// The size of a VAG header, 64 bytes for the long one and 48 for the short one.
+fn vag_header_size(file: &[u8]) -> usize {
+    let data_size = u32::from_be_bytes([file[12], file[13], file[14], file[15]]) as usize;
+    if data_size + 64 == file.len() {
+        64
+    } else {
+        48
+    }
+}
+
+fn loop_points(path: &Path, lb: u32, le: u32) -> LoopPoints {
+    LoopPoints::new(path.file_name().unwrap().to_string_lossy(), lb, le)
+}
+
+fn collect_ads(dir: &Path, recursive: bool, paths: &mut Vec<PathBuf>) {
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            if recursive {
+                collect_ads(&path, recursive, paths);
+            }
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("ads"))
+        {
+            paths.push(path);
+        }
+    }
+}
+
+/// Splits the body of an ADS file into its channels, returning them along with the codec.
+fn read_channels(ads_file: &[u8]) -> Result<(u32, Vec<Vec<u8>>), String> {
+    if ads_file.get(0..4) == Some(&VAG_MAGIC_NUMBER) && ads_file.len() >= 48 {
+        return Ok((0x10, vec![ads_file[vag_header_size(ads_file)..].to_vec()]));
+    }
+    if ads_file.len() < 0x28 || ads_file[0..4] != MAGIC_NUMBER {
+        return Err(format!(
+            "Invalid magic number, expected {MAGIC_NUMBER:?}, found {:?}",
+            &ads_file[0..4.min(ads_file.len())]
+        ));
+    }
+
+    let body_size = load_bytes(&ads_file[0x24..]) as usize;
     let codec = load_bytes(&ads_file[8..]);
-    if codec != 0x10 {
-        return None;
+    if codec != 0x10 && codec != 0x01 {
+        return Err(format!("Unsupported codec {codec:#x}"));
     }
 
-    let channel_number = load_bytes(&ads_file[0x10..]);
-    let step_size = 16;
+    let channel_number = load_bytes(&ads_file[0x10..]).max(1) as usize;
+    let interleave = load_bytes(&ads_file[0x14..]) as usize;
 
     let body = &ads_file[0x28..];
+    if body_size != body.len() {
+        eprintln!(
+            "Body size is {body_size} bytes, but the file has {} bytes of body",
+            body.len()
+        );
+    }
+    let body = &body[..body_size.min(body.len())];
 
-    body.chunks(step_size as usize)
-        .enumerate()
-        .find_map(|(i, x)| {
-            if x[1] == 6 {
-                Some((
-                    i as u32 * 28 / channel_number,
-                    body_size / 16 * 28 / channel_number - 1,
-                ))
-            } else {
-                None
+    Ok((codec, split_channels(body, channel_number, interleave)))
+}
+
+/// Finds the loop markers of a stream, or `None` if it doesn't loop.
+///
+/// `SONY_4BIT_ADPCM` streams get their loop from the flags, while `PCM16_LE` streams get searched
+/// for the most seamless loop start.
+fn find_loops(ads_file: &[u8], search: &PcmSearch) -> Result<Option<(u32, u32)>, String> {
+    let (codec, channels) = read_channels(ads_file)?;
+    if codec == 0x01 {
+        let samples = mix_pcm(&channels);
+        let candidates = search.candidates(&samples);
+        for candidate in &candidates {
+            eprintln!(
+                "Candidate: {} {} (confidence {:.3})",
+                candidate.start, candidate.end, candidate.confidence
+            );
+        }
+        return Ok(candidates
+            .first()
+            .filter(|candidate| candidate.confidence >= search.min_confidence)
+            .map(|candidate| (candidate.start, candidate.end)));
+    }
+
+    let loops = channels
+        .iter()
+        .map(|channel| flag_loop(channel))
+        .collect::<Vec<_>>();
+    if loops.iter().any(|x| *x != loops[0]) {
+        eprintln!("Channels have different loop points:");
+        for (i, channel_loop) in loops.iter().enumerate() {
+            match channel_loop {
+                Some((lb, le)) => eprintln!("Channel {i}: {lb} {le}"),
+                None => eprintln!("Channel {i}: no loop"),
             }
+        }
+    }
+
+    Ok(loops[0])
+}
+
+fn split_channels(body: &[u8], channels: usize, interleave: usize) -> Vec<Vec<u8>> {
+    if channels == 1 || interleave == 0 {
+        return vec![body.to_vec()];
+    }
+    let mut streams = vec![vec![]; channels];
+    for (i, block) in body.chunks(interleave).enumerate() {
+        streams[i % channels].extend_from_slice(block);
+    }
+    streams
+}
+
+/// How many samples before the loop start and end get compared.
+const MATCH_WINDOW: usize = 256;
+/// The fewest samples before the loop start that get compared, below which only the jump from
+/// the end to the start gets scored.
+const MIN_MATCH_WINDOW: usize = 16;
+
+struct PcmCandidate {
+    start: u32,
+    end: u32,
+    confidence: f64,
+}
+
+impl PcmSearch {
+    /// Finds the best loop starts for a loop ending on the last sample, best first.
+    ///
+    /// Only the starts that cross zero the same way as the end get compared, by correlating the
+    /// samples leading into them, so that jumping back sounds like the stream carrying on.
+    fn candidates(&self, samples: &[f64]) -> Vec<PcmCandidate> {
+        if samples.len() < MATCH_WINDOW * 2 + 2 {
+            return vec![];
+        }
+        let end = samples.len() - 1;
+        let first = (self.window_start as usize).max(MATCH_WINDOW + 1);
+        let last = self
+            .window_end
+            .map_or(end - MATCH_WINDOW, |x| x as usize)
+            .min(end - MATCH_WINDOW);
+
+        let mut candidates = (first..=last)
+            .filter(|start| {
+                samples[start - 1].signum() == samples[end].signum()
+                    && (samples[*start] - samples[start - 1]).signum()
+                        == (samples[end] - samples[end - 1]).signum()
+            })
+            .map(|start| PcmCandidate {
+                start: start as u32,
+                end: end as u32,
+                confidence: loop_confidence(samples, start, end),
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+
+        // Neighbouring samples of a good candidate are just as good, so only keep distinct ones
+        let mut distinct: Vec<PcmCandidate> = vec![];
+        for candidate in candidates {
+            if distinct.len() == self.candidates {
+                break;
+            }
+            if distinct
+                .iter()
+                .all(|x| x.start.abs_diff(candidate.start) as usize > MATCH_WINDOW)
+            {
+                distinct.push(candidate);
+            }
+        }
+        distinct
+    }
+}
+
+/// How seamless jumping from `end` back to `start` is, from 0 to 1.
+///
+/// Loops starting in the first samples compare as many samples as there are before the start.
+fn loop_confidence(samples: &[f64], start: usize, end: usize) -> f64 {
+    if start > end || end >= samples.len() {
+        return 0.0;
+    }
+    let window = MATCH_WINDOW.min(start);
+    if window < MIN_MATCH_WINDOW {
+        return jump_confidence(samples, start, end);
+    }
+    let before_start = &samples[start - window..start];
+    let before_end = &samples[end + 1 - window..=end];
+
+    let energy = |x: &[f64]| x.iter().map(|x| x * x).sum::<f64>();
+    let (start_energy, end_energy) = (energy(before_start), energy(before_end));
+    if start_energy == 0.0 && end_energy == 0.0 {
+        return 1.0;
+    }
+    if start_energy == 0.0 || end_energy == 0.0 {
+        return 0.0;
+    }
+    let correlation = before_start
+        .iter()
+        .zip(before_end)
+        .map(|(a, b)| a * b)
+        .sum::<f64>()
+        / (start_energy * end_energy).sqrt();
+    let balance = start_energy.min(end_energy) / start_energy.max(end_energy);
+    correlation.max(0.0) * balance.sqrt()
+}
+
+/// How smoothly the loop start follows the loop end, from 0 to 1.
+///
+/// The sample after the end is predicted from the slope leading into it, and the error of taking
+/// the start instead is compared with the usual error of that prediction before the end.
+fn jump_confidence(samples: &[f64], start: usize, end: usize) -> f64 {
+    let predict = |i: usize| match i {
+        1 => samples[0],
+        _ => 2.0 * samples[i - 1] - samples[i - 2],
+    };
+    let error = (samples[start] - predict(end + 1)).abs();
+    let before_end = (end + 1).saturating_sub(MATCH_WINDOW).max(1)..=end;
+    let count = before_end.clone().count();
+    let usual_error = before_end
+        .map(|i| (samples[i] - predict(i)).abs())
+        .sum::<f64>()
+        / count.max(1) as f64;
+    if error <= usual_error {
+        1.0
+    } else {
+        usual_error / error
+    }
+}
+
+/// Mixes the channels of a `PCM16_LE` stream down to one.
+fn mix_pcm(channels: &[Vec<u8>]) -> Vec<f64> {
+    let length = channels.iter().map(Vec::len).min().unwrap_or_default() / 2;
+    (0..length)
+        .map(|i| {
+            channels
+                .iter()
+                .map(|channel| f64::from(i16::from_le_bytes([channel[i * 2], channel[i * 2 + 1]])))
+                .sum::<f64>()
+                / channels.len() as f64
         })
+        .collect()
+}
+
+/// Reports how seamless every loop of a `loop.txt` file is, reading the `ads` files next to it.
+fn check_loops(loop_file: &Path) {
+    let dir = loop_file.parent().unwrap();
+    let text = std::fs::read_to_string(loop_file).unwrap();
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let mut parts = line.trim().splitn(3, ' ');
+        let (Some(Ok(lb)), Some(Ok(le)), Some(name)) = (
+            parts.next().map(str::parse::<usize>),
+            parts.next().map(str::parse::<usize>),
+            parts.next(),
+        ) else {
+            eprintln!("Invalid line: {line}");
+            continue;
+        };
+
+        let path = dir.join(name).with_extension("ads");
+        let channels = match std::fs::read(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|file| read_channels(&file))
+        {
+            Ok((0x01, channels)) => channels,
+            Ok((codec, _)) => {
+                eprintln!("{}: unsupported codec {codec:#x}", path.to_string_lossy());
+                continue;
+            }
+            Err(err) => {
+                eprintln!("{}: {err}", path.to_string_lossy());
+                continue;
+            }
+        };
+
+        let confidence = loop_confidence(&mix_pcm(&channels), lb, le);
+        println!(
+            "{lb} {le} {name}: confidence {confidence:.3}{}",
+            if confidence < 0.9 {
+                ", possible click"
+            } else {
+                ""
+            }
+        );
+    }
 }
 
 fn load_bytes(bytes: &[u8]) -> u32 {
     u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
 }
+
+#[test]
+fn stereo_loops() {
+    let search = PcmSearch {
+        window_start: 0,
+        window_end: None,
+        candidates: 1,
+        min_confidence: 0.9,
+    };
+    let mut file = [
+        MAGIC_NUMBER,
+        [0x18, 0, 0, 0],
+        [0x10, 0, 0, 0],
+        [0x44, 0xac, 0, 0],
+        [2, 0, 0, 0],
+        [0x20, 0, 0, 0],
+        [0xff; 4],
+        [0xff; 4],
+        [0x53, 0x53, 0x62, 0x64],
+        [0x80, 0, 0, 0],
+    ]
+    .concat();
+    // Two frames per block, with the loop between the second and fourth frame of each channel
+    for flag in [0, 6, 0, 6, 2, 3, 2, 3] {
+        file.extend([0, flag]);
+        file.extend([0; 14]);
+    }
+    assert_eq!(find_loops(&file, &search), Ok(Some((28, 4 * 28 - 1))));
+
+    // The first channel wins when they disagree
+    file[0x28 + 0x20 + 1] = 6;
+    file[0x28 + 0x30 + 1] = 0;
+    assert_eq!(find_loops(&file, &search), Ok(Some((28, 4 * 28 - 1))));
+    let channels = split_channels(&file[0x28..], 2, 0x20);
+    assert_eq!(flag_loop(&channels[1]), Some((0, 4 * 28 - 1)));
+
+    // Without an end flag, the loop goes until the end of the channel
+    file[0x28 + 0x50 + 1] = 2;
+    file.truncate(0x28 + 0x60);
+    assert_eq!(find_loops(&file, &search), Ok(Some((28, 4 * 28 - 1))));
+
+    file[8] = 0x02;
+    assert!(find_loops(&file, &search).is_err());
+}
+
+#[test]
+fn pcm_loops() {
+    let search = PcmSearch {
+        window_start: 0,
+        window_end: None,
+        candidates: 1,
+        min_confidence: 0.9,
+    };
+    // A sine wave with a period of 100 samples, after 1000 samples of noise
+    let samples = (0..5000)
+        .map(|i| {
+            if i < 1000 {
+                f64::from((i * 7919) % 2000) - 1000.0
+            } else {
+                (f64::from(i) * std::f64::consts::TAU / 100.0).sin() * 10000.0
+            }
+        })
+        .collect::<Vec<_>>();
+    let best = &search.candidates(&samples)[0];
+    assert_eq!(best.end, 4999);
+    assert_eq!((best.end + 1 - best.start) % 100, 0);
+    assert!(best.start >= 1000 + MATCH_WINDOW as u32);
+    assert!(best.confidence > 0.99);
+    assert!(loop_confidence(&samples, 1350, 4999) < 0.5);
+
+    // Loops from the very start are scored by the jump back to it
+    let sine = (0..5000)
+        .map(|i| (f64::from(i) * std::f64::consts::TAU / 100.0).sin() * 10000.0)
+        .collect::<Vec<_>>();
+    assert!(loop_confidence(&sine, 0, 4999) > 0.9);
+    assert!(loop_confidence(&sine, 100, 4999) > 0.9);
+    assert!(loop_confidence(&sine, 0, 4974) < 0.1);
+}
+
+#[test]
+fn vag_loops() {
+    let mut file = [VAG_MAGIC_NUMBER, [0, 0, 0, 3], [0; 4], 64u32.to_be_bytes()].concat();
+    file.resize(48 + 64, 0);
+    let search = PcmSearch {
+        window_start: 0,
+        window_end: None,
+        candidates: 1,
+        min_confidence: 0.9,
+    };
+    assert_eq!(find_loops(&file, &search), Ok(None));
+
+    write_loops(&mut file, 28, 3 * 28 - 1).unwrap();
+    let flags = file[48..]
+        .chunks_exact(16)
+        .map(|x| x[1])
+        .collect::<Vec<_>>();
+    assert_eq!(flags, [0, 6, 3, 0]);
+    assert_eq!(find_loops(&file, &search), Ok(Some((28, 3 * 28 - 1))));
+    assert!(write_loops(&mut file, 28, 4 * 28).is_err());
+
+    // A loop within a single frame reads back too
+    write_loops(&mut file, 2 * 28, 3 * 28 - 1).unwrap();
+    assert_eq!(file[48 + 2 * 16 + 1], 7);
+    assert_eq!(flag_loop(&file[48..]), Some((2 * 28, 3 * 28 - 1)));
+    assert_eq!(find_loops(&file, &search), Ok(Some((2 * 28, 3 * 28 - 1))));
+}
@@ -0,0 +1,297 @@
+use std::{fmt::Display, fs::File, io::Write, num::NonZeroU32, path::PathBuf};
+
+use clap::Parser;
+use spuadpcm::decode;
+
+/// How many bytes from the start of the stream get analyzed.
+const PROBE_SIZE: usize = 0x100000;
+const MAX_CHANNELS: u32 = 8;
+
+#[derive(Parser)]
+#[clap(version)]
+struct Args {
+    /// The headerless stream to analyze.
+    input: PathBuf,
+    /// How many candidates to list.
+    #[clap(short = 'n', long, default_value_t = 5)]
+    top: usize,
+    /// Writes an ADS file with the best candidate and this samplerate.
+    #[clap(short = 'r', long)]
+    sample_rate: Option<NonZeroU32>,
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Codec {
+    Pcm16le,
+    Vag,
+}
+
+impl Codec {
+    fn number(self) -> u32 {
+        match self {
+            Self::Pcm16le => 0x01,
+            Self::Vag => 0x10,
+        }
+    }
+
+    /// The smallest amount of bytes the body of a channel can be split in.
+    fn block_size(self) -> usize {
+        match self {
+            Self::Pcm16le => 2,
+            Self::Vag => 16,
+        }
+    }
+}
+
+impl Display for Codec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Pcm16le => f.pad("pcm16le"),
+            Self::Vag => f.pad("vag"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Candidate {
+    codec: Codec,
+    channels: u32,
+    interleave: u32,
+    /// How likely the layout is, from 0 to 1.
+    score: f64,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let file = std::fs::read(&args.input).unwrap();
+    let candidates = probe(&file);
+    if candidates.is_empty() {
+        eprintln!("The stream is too short to be analyzed");
+        std::process::exit(1);
+    }
+
+    println!("Rank  Codec    Channels  Interleave  Score");
+    for (i, candidate) in candidates.iter().take(args.top).enumerate() {
+        println!(
+            "{:<4}  {:<7}  {:<8}  {:<10}  {:.4}",
+            i + 1,
+            candidate.codec,
+            candidate.channels,
+            format!("{:#x}", candidate.interleave),
+            candidate.score
+        );
+    }
+
+    let best = &candidates[0];
+    let Some(sample_rate) = args.sample_rate else {
+        println!(
+            "Best: adsheader {} {} [samplerate] {} {}",
+            args.input.to_string_lossy(),
+            best.channels,
+            best.interleave,
+            best.codec
+        );
+        return;
+    };
+
+    let header = [
+        [0x53, 0x53, 0x68, 0x64],
+        [0x18, 0, 0, 0],
+        best.codec.number().to_le_bytes(),
+        sample_rate.get().to_le_bytes(),
+        best.channels.to_le_bytes(),
+        best.interleave.to_le_bytes(),
+        [0xff; 4],
+        [0xff; 4],
+        [0x53, 0x53, 0x62, 0x64],
+        (file.len() as u32).to_le_bytes(),
+    ];
+    let mut output = File::create(
+        args.output
+            .unwrap_or_else(|| args.input.with_extension("ads")),
+    )
+    .unwrap();
+    output
+        .write_all(&header.into_iter().flatten().collect::<Vec<_>>())
+        .unwrap();
+    output.write_all(&file).unwrap();
+}
+
+/// Scores every layout the stream could have, best first.
+fn probe(file: &[u8]) -> Vec<Candidate> {
+    let mut candidates = vec![];
+    for codec in [Codec::Pcm16le, Codec::Vag] {
+        for channels in 1..=MAX_CHANNELS {
+            let interleaves = if channels == 1 {
+                vec![0]
+            } else {
+                let mut interleaves = (4..=15).map(|x| 1 << x).collect::<Vec<_>>();
+                if codec == Codec::Pcm16le {
+                    interleaves.insert(0, 2);
+                }
+                interleaves
+            };
+            for interleave in interleaves {
+                if let Some(score) = score(file, codec, channels as usize, interleave as usize) {
+                    candidates.push(Candidate {
+                        codec,
+                        channels,
+                        interleave,
+                        score,
+                    });
+                }
+            }
+        }
+    }
+
+    // Fewer channels win ties, since silence fits every layout
+    candidates.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then(a.channels.cmp(&b.channels))
+    });
+    candidates
+}
+
+/// Scores a layout from 0 to 1, or `None` if the stream doesn't fit it at all.
+fn score(file: &[u8], codec: Codec, channels: usize, interleave: usize) -> Option<f64> {
+    let row = if channels == 1 {
+        codec.block_size()
+    } else {
+        interleave * channels
+    };
+    let body = &file[..file.len().min(PROBE_SIZE) / row * row];
+    if body.len() < row * 2 || !file.len().is_multiple_of(codec.block_size() * channels) {
+        return None;
+    }
+
+    let streams = split_channels(body, channels, interleave);
+    let (validity, decoded): (f64, Vec<Vec<i32>>) = match codec {
+        Codec::Pcm16le => (
+            1.0,
+            streams
+                .iter()
+                .map(|stream| {
+                    stream
+                        .chunks_exact(2)
+                        .map(|x| i32::from(i16::from_le_bytes([x[0], x[1]])))
+                        .collect()
+                })
+                .collect(),
+        ),
+        Codec::Vag => (
+            adpcm_validity(&streams),
+            streams
+                .iter()
+                .map(|stream| decode(stream).into_iter().map(i32::from).collect())
+                .collect(),
+        ),
+    };
+
+    Some(validity * continuity(&decoded))
+}
+
+fn split_channels(body: &[u8], channels: usize, interleave: usize) -> Vec<Vec<u8>> {
+    if channels == 1 {
+        return vec![body.to_vec()];
+    }
+    let mut streams = vec![vec![]; channels];
+    for (i, block) in body.chunks(interleave).enumerate() {
+        streams[i % channels].extend_from_slice(block);
+    }
+    streams
+}
+
+/// How many frames have valid shift/filter and flag bytes, with end flags only on the last frame of
+/// every channel.
+fn adpcm_validity(streams: &[Vec<u8>]) -> f64 {
+    let mut frames = 0;
+    let mut valid = 0;
+    for stream in streams {
+        let frame_count = stream.len() / 16;
+        for (i, frame) in stream.chunks_exact(16).enumerate() {
+            frames += 1;
+            let early_end = frame[1] & 1 != 0 && i + 1 != frame_count;
+            if frame[0] >> 4 <= 4 && frame[0] & 0xf <= 12 && frame[1] <= 7 && !early_end {
+                valid += 1;
+            }
+        }
+    }
+    if frames == 0 {
+        0.0
+    } else {
+        f64::from(valid) / f64::from(frames)
+    }
+}
+
+/// How smooth the decoded channels are, from 0 for noise to 1 for a flat line.
+///
+/// A wrong layout splits the channels in the wrong places, which adds a jump at every block.
+fn continuity(channels: &[Vec<i32>]) -> f64 {
+    let mut jumps = 0.0;
+    let mut level = 0.0;
+    for channel in channels {
+        for pair in channel.windows(2) {
+            jumps += f64::from((pair[1] - pair[0]).abs());
+            level += f64::from(pair[1].abs());
+        }
+    }
+    if level == 0.0 {
+        return 1.0;
+    }
+    // Two unrelated samples are 4/3 of the average level apart
+    (1.0 - jumps / level * 0.75).max(0.0)
+}
+
+#[test]
+fn stereo_pcm() {
+    let mut file = vec![];
+    for block in 0..16 {
+        for channel in 0..2 {
+            for i in 0..0x100 {
+                let t = f64::from(block * 0x100 + i) / 50.0;
+                let sample = if channel == 0 { t.sin() } else { t.cos() };
+                file.extend(((sample * 10000.0) as i16).to_le_bytes());
+            }
+        }
+    }
+    let best = &probe(&file)[0];
+    assert_eq!(best.codec, Codec::Pcm16le);
+    assert_eq!(best.channels, 2);
+    assert_eq!(best.interleave, 0x200);
+}
+
+#[test]
+fn stereo_vag() {
+    // Frames with no filter or shift, so every nibble is a sample in steps of 4096
+    let frames = |channel: usize| {
+        let samples = (0..16 * 16 * 28)
+            .map(|i| {
+                let t = f64::from(i) / 50.0;
+                let sample = if channel == 0 { t.sin() } else { t.cos() };
+                ((sample * 7.0).round() as i8 & 0xf) as u8
+            })
+            .collect::<Vec<_>>();
+        samples
+            .chunks_exact(28)
+            .flat_map(|frame| {
+                [0, 0]
+                    .into_iter()
+                    .chain(frame.chunks_exact(2).map(|pair| pair[0] | pair[1] << 4))
+            })
+            .collect::<Vec<_>>()
+    };
+    let (left, right) = (frames(0), frames(1));
+    let file = left
+        .chunks_exact(0x100)
+        .zip(right.chunks_exact(0x100))
+        .flat_map(|(left, right)| [left, right].concat())
+        .collect::<Vec<_>>();
+    let best = &probe(&file)[0];
+    assert_eq!(best.codec, Codec::Vag);
+    assert_eq!(best.channels, 2);
+    assert_eq!(best.interleave, 0x100);
+}
@@ -272,7 +272,7 @@ enum Token<'a> {
     GlobalEnding,
 }
 
-fn parse_file(bytes: &[u8]) -> Vec<Token> {
+fn parse_file(bytes: &[u8]) -> Vec<Token<'_>> {
     let mut i = 0;
     let mut tokens = vec![];
     while i < bytes.len() - 2 {
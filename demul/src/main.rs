@@ -1,6 +1,23 @@
 use std::{fs::File, io::Write, path::PathBuf};
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use serde::Serialize;
+use spuadpcm::{decode, interleaved, FILTERS};
+
+/// The step sizes of IMA ADPCM, indexed by the step index.
+const IMA_STEPS: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449,
+    494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272,
+    2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493,
+    10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767,
+];
+/// How the step index of IMA ADPCM changes after each nibble, without its sign bit.
+const IMA_INDEX_CHANGES: [i32; 8] = [-1, -1, -1, -1, 2, 4, 6, 8];
+/// The size of an IMA ADPCM block of a channel: a 4-byte header followed by 64 nibbles.
+const IMA_BLOCK_SIZE: usize = 36;
+/// The usual sizes of a mono MS ADPCM block, which has a 7-byte header.
+const MS_ADPCM_BLOCK_SIZES: [usize; 4] = [256, 512, 1024, 2048];
 
 #[derive(Parser)]
 struct Args {
@@ -9,135 +26,672 @@ struct Args {
     /// The output directory
     #[clap(short, long)]
     output: Option<PathBuf>,
+    /// The format of the audio channels.
+    #[clap(short, long, default_value = "bin")]
+    format: Format,
+    /// Joins every pair of channels into one stereo file.
+    #[clap(short, long)]
+    stereo: bool,
+    /// The interleave in bytes of the stereo ADS files.
+    #[clap(short, long, default_value_t = 0x800)]
+    interleave: u32,
+    /// The format of the chunk timeline.
+    #[clap(short, long, default_value = "csv")]
+    timeline: TimelineFormat,
+    /// The codec of the audio channels, detected from the audio if not given. MS ADPCM isn't
+    /// supported, and is refused when detected.
+    #[clap(short, long)]
+    codec: Option<Codec>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// Raw channels, along with a rate file for `adsheader`.
+    Bin,
+    Ads,
+    Wav,
+    Vag,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Codec {
+    /// PlayStation 4-bit ADPCM, in 16-byte frames of 28 samples.
+    Vag,
+    Pcm16le,
+    /// IMA ADPCM in 36-byte blocks of 64 samples, as used by the Xbox.
+    Ima,
+}
+
+impl Codec {
+    /// Guesses the codec of a channel from the structure of its blocks, returning `None` if it
+    /// has the structure of neither ADPCM codec.
+    fn detect(channel: &[u8]) -> Option<Self> {
+        let channel = &channel[..channel.len().min(0x10000)];
+        let valid_share = |block_size: usize, valid: fn(&[u8]) -> bool| {
+            let blocks = channel.chunks_exact(block_size);
+            let count = blocks.len();
+            blocks.filter(|block| valid(block)).count() as f64 / count.max(1) as f64
+        };
+
+        // The shift and filter of every frame, and its flags
+        let vag = valid_share(16, |frame| {
+            frame[0] & 0xf <= 12 && frame[0] >> 4 < FILTERS.len() as u8 && frame[1] <= 7
+        });
+        // The step index of every block, followed by a zero byte
+        let ima = valid_share(IMA_BLOCK_SIZE, |block| block[2] <= 88 && block[3] == 0);
+        if vag >= 0.95 {
+            Some(Self::Vag)
+        } else if ima >= 0.95 && channel.len().is_multiple_of(IMA_BLOCK_SIZE) {
+            Some(Self::Ima)
+        } else {
+            None
+        }
+    }
+
+    /// The codec number of ADS headers and `adsheader`.
+    fn ads_number(self) -> Option<u32> {
+        match self {
+            Self::Vag => Some(0x10),
+            Self::Pcm16le => Some(0x01),
+            Self::Ima => None,
+        }
+    }
+
+    /// The amount of samples in this many bytes of a channel.
+    fn sample_count(self, bytes: u64) -> u64 {
+        match self {
+            Self::Vag => bytes / 16 * 28,
+            Self::Pcm16le => bytes / 2,
+            Self::Ima => bytes / IMA_BLOCK_SIZE as u64 * 64,
+        }
+    }
+
+    fn decode(self, channel: &[u8]) -> Vec<i16> {
+        match self {
+            Self::Vag => decode(channel),
+            Self::Pcm16le => channel
+                .chunks_exact(2)
+                .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+                .collect(),
+            Self::Ima => decode_ima(channel),
+        }
+    }
+}
+
+/// The block size of a channel that looks like mono MS ADPCM, which is detected so that it doesn't
+/// get taken as PCM16LE, but isn't supported.
+///
+/// Every block starts with a predictor from 0 to 6 followed by a positive step of at least 16.
+fn ms_adpcm_block_size(channel: &[u8]) -> Option<usize> {
+    let channel = &channel[..channel.len().min(0x10000)];
+    MS_ADPCM_BLOCK_SIZES.into_iter().find(|block_size| {
+        let blocks = channel.chunks_exact(*block_size);
+        let count = blocks.len();
+        let valid = blocks
+            .filter(|block| {
+                block[0] <= 6 && (16..=0x7FFF).contains(&i16::from_le_bytes([block[1], block[2]]))
+            })
+            .count();
+        count >= 8 && valid as f64 / count as f64 >= 0.95
+    })
+}
+
+impl std::fmt::Display for Codec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Vag => "SONY_4BIT_ADPCM",
+            Self::Pcm16le => "PCM16LE",
+            Self::Ima => "IMA_ADPCM",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum TimelineFormat {
+    Csv,
+    Json,
+}
+
+/// A chunk of the MUL file, with where it is in the file and in the audio.
+#[derive(Serialize)]
+struct TimelineEntry {
+    kind: &'static str,
+    /// The number identifying the kind of chunk.
+    #[serde(rename = "type")]
+    variant: u32,
+    /// The offset of the chunk header in the file.
+    offset: usize,
+    /// The size of the chunk contents, without the headers.
+    size: u32,
+    /// The bytes between the size and the contents, in hex, so that `mkmul` can write them back.
+    header: String,
+    /// The amount of bytes per channel in the audio chunks before this one.
+    audio_offset: u64,
+    /// The amount of samples per channel in the audio chunks before this one.
+    sample: u64,
+    /// The same as `sample`, in seconds.
+    time: f64,
+    /// The file a data chunk or a chunk of an unknown type was written to.
+    file: Option<String>,
 }
 
 fn main() {
     let args = Args::parse();
 
+    if args.stereo && matches!(args.format, Format::Bin | Format::Vag) {
+        eprintln!("Stereo files can only be written as ADS or WAV");
+        std::process::exit(1);
+    }
+    if args.stereo && (args.interleave == 0 || !args.interleave.is_multiple_of(16)) {
+        eprintln!("The interleave must be a non-zero multiple of 16 bytes");
+        std::process::exit(1);
+    }
+
     let mul_file = std::fs::read(&args.input).unwrap();
 
-    let sample_rate = u32::from_le_bytes([mul_file[0], mul_file[1], mul_file[2], mul_file[3]]);
-    let channels = u32::from_le_bytes([mul_file[12], mul_file[13], mul_file[14], mul_file[15]]);
+    let Some(header) = MulHeader::parse(&mul_file) else {
+        eprintln!("The file is too small to hold a MUL header");
+        std::process::exit(1);
+    };
+    let sample_rate = header.sample_rate;
+    let channels = header.channels;
+    if channels == 0 {
+        eprintln!("The MUL header has no audio channels");
+        std::process::exit(1);
+    }
 
-    let mut body = &mul_file[0x800..];
+    let project_name = args.input.file_stem().unwrap().to_string_lossy();
+    let mut body = &mul_file[MulHeader::SIZE..];
     let mut audio_slices = (0..channels).map(|_| vec![]).collect::<Vec<_>>();
-    let mut data_slices = vec![];
+    // The data chunks and chunks of unknown types, with the names of their files
+    let mut blobs = vec![];
     let mut audio_chunks = 0;
+    let mut data_chunks = 0;
     let mut padding_chunks = 0;
-    while let Some(current_chunk) = Chunk::parse(&mut body) {
-        match current_chunk {
+    let mut unknown_chunks = 0;
+    let mut timeline = vec![];
+    let mut audio_offset = 0;
+    loop {
+        let offset = mul_file.len() - body.len();
+        let Some((current_chunk, chunk_header)) = Chunk::parse(&mut body) else {
+            if offset < mul_file.len() {
+                eprintln!(
+                    "Chunk at {offset:#x} is truncated: only {} bytes left for its header",
+                    mul_file.len() - offset
+                );
+            }
+            break;
+        };
+        let size = current_chunk.size();
+        let variant = current_chunk.variant();
+        let bytes = get_bytes(&mut body, size as usize);
+        if bytes.len() < size as usize {
+            eprintln!(
+                "Chunk at {offset:#x} is truncated: {size} bytes expected, {} found",
+                bytes.len()
+            );
+        }
+        let chunk_audio_offset = audio_offset;
+        let (kind, file) = match current_chunk {
             Chunk::Audio { size } => {
                 audio_chunks += 1;
                 let split_size = size / channels;
-                let bytes = get_bytes(&mut body, size as usize);
-                for (i, slice) in bytes.chunks(split_size as usize).enumerate() {
-                    audio_slices[i].push(slice);
+                if split_size * channels != size {
+                    eprintln!(
+                        "Audio chunk at {offset:#x} has {size} bytes, which doesn't split evenly \
+                         into {channels} channels: ignoring the last {} bytes",
+                        size - split_size * channels
+                    );
                 }
+                if split_size > 0 {
+                    for (i, slice) in bytes
+                        .chunks(split_size as usize)
+                        .take(channels as usize)
+                        .enumerate()
+                    {
+                        audio_slices[i].push(slice);
+                    }
+                }
+                audio_offset += u64::from(split_size);
+                ("audio", None)
+            }
+            Chunk::Data { .. } => {
+                let name = format!("{project_name}_data_{data_chunks}_{offset:#x}.bin");
+                data_chunks += 1;
+                blobs.push((name.clone(), bytes));
+                ("data", Some(name))
             }
-            Chunk::Data { size } => data_slices.push(get_bytes(&mut body, size as usize)),
-            Chunk::Padding { size } => {
+            Chunk::Padding { .. } => {
                 padding_chunks += 1;
-                get_bytes(&mut body, size as usize);
+                ("padding", None)
             }
-        }
+            Chunk::Unknown { variant, .. } => {
+                eprintln!("Chunk at {offset:#x} has the unknown type {variant}");
+                let name =
+                    format!("{project_name}_unknown{variant}_{unknown_chunks}_{offset:#x}.bin");
+                unknown_chunks += 1;
+                blobs.push((name.clone(), bytes));
+                ("unknown", Some(name))
+            }
+        };
+        timeline.push(TimelineEntry {
+            kind,
+            variant,
+            offset,
+            size,
+            header: chunk_header
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect(),
+            audio_offset: chunk_audio_offset,
+            sample: 0,
+            time: 0.0,
+            file,
+        });
     }
 
-    let project_name = args.input.file_stem().unwrap().to_string_lossy();
     let output_dir = args.output.unwrap_or_else(|| args.input.with_extension(""));
     std::fs::create_dir(&output_dir).unwrap();
-    if !audio_slices.is_empty() {
-        for (i, slices) in audio_slices.iter().enumerate() {
-            let mut out = File::create(format!(
-                "{}/{}_audio_ch{i}.bin",
-                output_dir.to_string_lossy(),
-                project_name
-            ))
-            .unwrap();
-            out.write_all(
-                &slices
-                    .iter()
-                    .copied()
-                    .flatten()
-                    .copied()
-                    .collect::<Vec<_>>(),
-            )
-            .unwrap();
-        }
-    }
-    if !data_slices.is_empty() {
-        let mut out = File::create(format!(
-            "{}/{}_data.bin",
-            output_dir.to_string_lossy(),
-            project_name
-        ))
-        .unwrap();
-        out.write_all(
-            &data_slices
+    let audio = audio_slices
+        .iter()
+        .map(|slices| {
+            slices
                 .iter()
-                .flat_map(|data| data.iter())
                 .copied()
-                .collect::<Vec<_>>(),
-        )
-        .unwrap();
+                .flatten()
+                .copied()
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let detected = args.codec.is_none();
+    let codec = args.codec.unwrap_or_else(|| {
+        let channel = audio.first().map(Vec::as_slice).unwrap_or_default();
+        Codec::detect(channel).unwrap_or_else(|| {
+            if let Some(block_size) = ms_adpcm_block_size(channel) {
+                eprintln!(
+                    "The audio looks like MS ADPCM in {block_size}-byte blocks, which isn't \
+                     supported (use -c to extract it as another codec anyway)"
+                );
+                std::process::exit(1);
+            }
+            eprintln!(
+                "The audio has neither SONY_4BIT_ADPCM frames nor IMA or MS ADPCM blocks, \
+                 assuming PCM16LE"
+            );
+            Codec::Pcm16le
+        })
+    });
+    if codec != Codec::Vag && args.format == Format::Vag {
+        eprintln!("Only SONY_4BIT_ADPCM channels can be written as VAG files");
+        std::process::exit(1);
+    }
+    if codec.ads_number().is_none() && args.format == Format::Ads {
+        eprintln!("{codec} channels can't be written as ADS files");
+        std::process::exit(1);
+    }
+    for entry in &mut timeline {
+        entry.sample = codec.sample_count(entry.audio_offset);
+        entry.time = entry.sample as f64 / f64::from(sample_rate.max(1));
     }
 
-    let mut rate_file = File::create(format!(
-        "{}/{project_name}_rate.txt",
-        output_dir.to_string_lossy()
-    ))
+    // Pairs of channels in stereo, or every channel on its own
+    let group_size = if args.stereo { 2 } else { 1 };
+    let groups = audio.chunks(group_size).enumerate().map(|(i, group)| {
+        let first = i * group_size;
+        let name = match group.len() {
+            1 => format!("{project_name}_audio_ch{first}"),
+            _ => format!("{project_name}_audio_ch{first}_ch{}", first + 1),
+        };
+        (name, group)
+    });
+    for (name, group) in groups {
+        let (bytes, extension) = match args.format {
+            Format::Bin => (group[0].clone(), "bin"),
+            Format::Vag => (vag_bytes(&group[0], sample_rate), "vag"),
+            Format::Ads => (
+                ads_bytes(group, &header, codec, args.interleave as usize),
+                "ads",
+            ),
+            Format::Wav => (wav_bytes(group, &header, codec), "wav"),
+        };
+        let mut out = File::create(output_dir.join(format!("{name}.{extension}"))).unwrap();
+        out.write_all(&bytes).unwrap();
+    }
+    std::fs::write(
+        output_dir.join(format!("{project_name}_header.bin")),
+        &mul_file[..MulHeader::SIZE],
+    )
     .unwrap();
-    for i in 0..channels {
-        write!(
-            &mut rate_file,
-            "{project_name}_audio_ch{i}.bin 1 {sample_rate} 0 16\r\n",
-        )
+    for (name, data) in &blobs {
+        let mut out = File::create(output_dir.join(name)).unwrap();
+        out.write_all(data).unwrap();
+    }
+    let timeline_file = match args.timeline {
+        TimelineFormat::Csv => {
+            let mut csv =
+                "kind,type,offset,size,header,audio_offset,sample,time,file\r\n".to_owned();
+            for entry in &timeline {
+                csv += &format!(
+                    "{},{},{:#x},{},{},{:#x},{},{:.3},{}\r\n",
+                    entry.kind,
+                    entry.variant,
+                    entry.offset,
+                    entry.size,
+                    entry.header,
+                    entry.audio_offset,
+                    entry.sample,
+                    entry.time,
+                    entry.file.as_deref().unwrap_or_default()
+                );
+            }
+            (csv, "csv")
+        }
+        TimelineFormat::Json => (serde_json::to_string_pretty(&timeline).unwrap(), "json"),
+    };
+    std::fs::write(
+        output_dir.join(format!("{project_name}_timeline.{}", timeline_file.1)),
+        timeline_file.0,
+    )
+    .unwrap();
+
+    if let (Format::Bin, Some(codec_number)) = (args.format, codec.ads_number()) {
+        let mut rate_file = File::create(format!(
+            "{}/{project_name}_rate.txt",
+            output_dir.to_string_lossy()
+        ))
         .unwrap();
+        for i in 0..channels {
+            write!(
+                &mut rate_file,
+                "{project_name}_audio_ch{i}.bin 1 {sample_rate} 0 {codec_number}\r\n",
+            )
+            .unwrap();
+        }
+    } else if args.format == Format::Bin {
+        eprintln!("No rate file was written, since adsheader doesn't support {codec}");
     }
 
     println!("MUL file");
     println!("Audio channels: {channels}");
     println!("Audio sample rate: {sample_rate}");
+    match detected {
+        true => println!("Audio codec: {codec} (detected)"),
+        false => println!("Audio codec: {codec}"),
+    }
+    println!("Audio length: {} samples", header.sample_count);
+    match header.loop_start {
+        Some(loop_start) => println!("Audio loop start: {loop_start}"),
+        None => println!("Audio loop start: none"),
+    }
+    if !header.unknown.is_empty() {
+        println!("Unknown header fields:");
+        for (offset, value) in &header.unknown {
+            println!("  {offset:#05x}: {value:#010x}");
+        }
+    }
     println!(
         "Total chunks: {}",
-        data_slices.len() + audio_chunks + padding_chunks
+        audio_chunks + data_chunks + padding_chunks + unknown_chunks
     );
-    println!("Data chunks: {}", data_slices.len());
+    println!("Data chunks: {data_chunks}");
     println!("Audio chunks: {audio_chunks}");
     println!("Padding chunks: {padding_chunks}");
+    if unknown_chunks > 0 {
+        println!("Unknown chunks: {unknown_chunks}");
+    }
+}
+
+/// The 0x800-byte header at the start of a MUL file.
+struct MulHeader {
+    sample_rate: u32,
+    /// The sample the audio loops back to, if it loops.
+    loop_start: Option<u32>,
+    /// The length of the audio in samples, per channel.
+    sample_count: u32,
+    channels: u32,
+    /// The offset and value of every other non-zero field.
+    unknown: Vec<(usize, u32)>,
+}
+
+impl MulHeader {
+    const SIZE: usize = 0x800;
+
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let header = bytes.get(..Self::SIZE)?;
+        let field = |offset: usize| {
+            u32::from_le_bytes([
+                header[offset],
+                header[offset + 1],
+                header[offset + 2],
+                header[offset + 3],
+            ])
+        };
+        Some(Self {
+            sample_rate: field(0x00),
+            loop_start: Some(field(0x04)).filter(|&start| start != u32::MAX),
+            sample_count: field(0x08),
+            channels: field(0x0C),
+            unknown: (0x10..Self::SIZE)
+                .step_by(4)
+                .map(|offset| (offset, field(offset)))
+                .filter(|&(_, value)| value != 0)
+                .collect(),
+        })
+    }
+
+    /// The loop start and the first sample after the loop, as stored in ADS headers.
+    fn ads_loop(&self) -> Option<(u32, u32)> {
+        self.loop_start
+            .filter(|&start| start < self.sample_count)
+            .map(|start| (start, self.sample_count))
+    }
+}
+
+/// An ADS file holding the channels, interleaved if there is more than one.
+fn ads_bytes(channels: &[Vec<u8>], header: &MulHeader, codec: Codec, interleave: usize) -> Vec<u8> {
+    let (body, interleave) = match channels {
+        [channel] => (channel.clone(), 0),
+        _ => (
+            interleaved(channels.iter().map(Vec::as_slice), interleave),
+            interleave as u32,
+        ),
+    };
+    let (loop_start, loop_end) = header.ads_loop().unwrap_or((u32::MAX, u32::MAX));
+    [
+        [0x53, 0x53, 0x68, 0x64],
+        [0x18, 0, 0, 0],
+        codec.ads_number().unwrap().to_le_bytes(),
+        header.sample_rate.to_le_bytes(),
+        (channels.len() as u32).to_le_bytes(),
+        interleave.to_le_bytes(),
+        loop_start.to_le_bytes(),
+        loop_end.to_le_bytes(),
+        [0x53, 0x53, 0x62, 0x64],
+        (body.len() as u32).to_le_bytes(),
+    ]
+    .into_iter()
+    .flatten()
+    .chain(body)
+    .collect()
+}
+
+fn vag_bytes(channel: &[u8], sample_rate: u32) -> Vec<u8> {
+    [
+        [0x56, 0x41, 0x47, 0x70],
+        [0, 0, 0, 3],
+        [0; 4],
+        (channel.len() as u32).to_be_bytes(),
+        sample_rate.to_be_bytes(),
+        [0; 4],
+        [0; 4],
+        [0; 4],
+        [0; 4],
+        [0; 4],
+        [0; 4],
+        [0; 4],
+    ]
+    .into_iter()
+    .flatten()
+    .chain(channel.iter().copied())
+    .collect()
+}
+
+/// A PCM16 WAV file with the channels decoded, padding the shorter ones with silence.
+///
+/// The audio is cut to the length in the header, and its loop is stored in a `smpl` chunk.
+fn wav_bytes(channels: &[Vec<u8>], header: &MulHeader, codec: Codec) -> Vec<u8> {
+    let decoded = channels
+        .iter()
+        .map(|channel| codec.decode(channel))
+        .collect::<Vec<_>>();
+    let mut length = decoded.iter().map(Vec::len).max().unwrap_or_default();
+    if header.sample_count != 0 {
+        length = length.min(header.sample_count as usize);
+    }
+    let samples = (0..length)
+        .flat_map(|i| {
+            decoded
+                .iter()
+                .map(move |channel| channel.get(i).copied().unwrap_or_default())
+        })
+        .flat_map(i16::to_le_bytes)
+        .collect::<Vec<_>>();
+
+    let smpl = match header.ads_loop() {
+        Some((loop_start, loop_end)) if (loop_end as usize) <= length => [
+            *b"smpl",
+            60u32.to_le_bytes(),
+            [0; 4],
+            [0; 4],
+            (1_000_000_000 / header.sample_rate.max(1)).to_le_bytes(),
+            60u32.to_le_bytes(),
+            [0; 4],
+            [0; 4],
+            [0; 4],
+            1u32.to_le_bytes(),
+            [0; 4],
+            [0; 4],
+            [0; 4],
+            loop_start.to_le_bytes(),
+            (loop_end - 1).to_le_bytes(),
+            [0; 4],
+            [0; 4],
+        ]
+        .concat(),
+        _ => vec![],
+    };
+
+    let sample_rate = header.sample_rate;
+    let channel_count = channels.len() as u16;
+    [
+        *b"RIFF",
+        (samples.len() as u32 + smpl.len() as u32 + 36).to_le_bytes(),
+        *b"WAVE",
+        *b"fmt ",
+        16u32.to_le_bytes(),
+    ]
+    .into_iter()
+    .flatten()
+    .chain(1u16.to_le_bytes())
+    .chain(channel_count.to_le_bytes())
+    .chain(sample_rate.to_le_bytes())
+    .chain((sample_rate * u32::from(channel_count) * 2).to_le_bytes())
+    .chain((channel_count * 2).to_le_bytes())
+    .chain(16u16.to_le_bytes())
+    .chain(*b"data")
+    .chain((samples.len() as u32).to_le_bytes())
+    .chain(samples)
+    .chain(smpl)
+    .collect()
+}
+
+/// Decodes a channel of IMA ADPCM blocks, each starting with the first sample and the step index.
+fn decode_ima(channel: &[u8]) -> Vec<i16> {
+    let mut samples = Vec::with_capacity(channel.len() / IMA_BLOCK_SIZE * 64);
+    for block in channel.chunks_exact(IMA_BLOCK_SIZE) {
+        let mut predictor = i32::from(i16::from_le_bytes([block[0], block[1]]));
+        let mut index = i32::from(block[2]).min(88);
+        for byte in &block[4..] {
+            for nibble in [byte & 0xf, byte >> 4] {
+                let step = IMA_STEPS[index as usize];
+                let mut diff = step >> 3;
+                if nibble & 4 != 0 {
+                    diff += step;
+                }
+                if nibble & 2 != 0 {
+                    diff += step >> 1;
+                }
+                if nibble & 1 != 0 {
+                    diff += step >> 2;
+                }
+                predictor = match nibble & 8 {
+                    0 => predictor + diff,
+                    _ => predictor - diff,
+                }
+                .clamp(i16::MIN.into(), i16::MAX.into());
+                index = (index + IMA_INDEX_CHANGES[(nibble & 7) as usize]).clamp(0, 88);
+                samples.push(predictor as i16);
+            }
+        }
+    }
+    samples
 }
 
 enum Chunk {
-    Audio { size: u32 },
-    Data { size: u32 },
-    Padding { size: u32 },
+    Audio {
+        size: u32,
+    },
+    Data {
+        size: u32,
+    },
+    Padding {
+        size: u32,
+    },
+    /// A chunk of a type not seen so far, assumed to have the same header as data chunks.
+    Unknown {
+        variant: u32,
+        size: u32,
+    },
 }
 
 impl Chunk {
-    fn parse(bytes: &mut &[u8]) -> Option<Self> {
+    /// Parses the header of a chunk, or returns `None` if the file ends before it does.
+    ///
+    /// The bytes between the size and the contents are returned along with it, which are 8 bytes
+    /// for every chunk, plus the 16-byte audio header for audio chunks.
+    fn parse<'a>(bytes: &mut &'a [u8]) -> Option<(Self, &'a [u8])> {
         let variant = parse_u32(bytes)?;
-        match variant {
-            0 => {
-                let audio = Self::Audio {
-                    size: parse_u32(bytes)? - 16,
-                };
-                get_bytes(bytes, 8 + 16);
-                Some(audio)
-            }
-            1 => {
-                let data = Self::Data {
-                    size: parse_u32(bytes)?,
-                };
-                get_bytes(bytes, 8);
-                Some(data)
-            }
-            2 => {
-                let data = Self::Padding {
-                    size: parse_u32(bytes)?,
-                };
-                get_bytes(bytes, 8);
-                Some(data)
-            }
-            _ => panic!("Unsupported variant: {variant}"),
+        let size = parse_u32(bytes)?;
+        let chunk = match variant {
+            0 => Self::Audio {
+                size: size.saturating_sub(16),
+            },
+            1 => Self::Data { size },
+            2 => Self::Padding { size },
+            _ => Self::Unknown { variant, size },
+        };
+        let header = match chunk {
+            Self::Audio { .. } => get_bytes(bytes, 8 + 16),
+            _ => get_bytes(bytes, 8),
+        };
+        Some((chunk, header))
+    }
+
+    fn variant(&self) -> u32 {
+        match self {
+            Self::Audio { .. } => 0,
+            Self::Data { .. } => 1,
+            Self::Padding { .. } => 2,
+            Self::Unknown { variant, .. } => *variant,
+        }
+    }
+
+    fn size(&self) -> u32 {
+        match self {
+            Self::Audio { size }
+            | Self::Data { size }
+            | Self::Padding { size }
+            | Self::Unknown { size, .. } => *size,
         }
     }
 }
@@ -153,8 +707,74 @@ fn parse_u32(bytes: &mut &[u8]) -> Option<u32> {
     data
 }
 
+/// Takes `count` bytes, or all of them if there are fewer left.
 fn get_bytes<'a>(bytes: &mut &'a [u8], count: usize) -> &'a [u8] {
-    let data = &bytes[0..count];
-    *bytes = &bytes[count..];
+    let (data, rest) = bytes.split_at(count.min(bytes.len()));
+    *bytes = rest;
     data
 }
+
+#[test]
+fn header() {
+    let mut bytes = vec![0; MulHeader::SIZE];
+    bytes[0x00..0x04].copy_from_slice(&22050u32.to_le_bytes());
+    bytes[0x04..0x08].copy_from_slice(&u32::MAX.to_le_bytes());
+    bytes[0x08..0x0C].copy_from_slice(&5600u32.to_le_bytes());
+    bytes[0x0C..0x10].copy_from_slice(&2u32.to_le_bytes());
+    bytes[0x38..0x3C].copy_from_slice(&1.0f32.to_bits().to_le_bytes());
+    let header = MulHeader::parse(&bytes).unwrap();
+    assert_eq!(header.loop_start, None);
+    assert_eq!(header.ads_loop(), None);
+    assert_eq!(header.unknown, [(0x38, 0x3f800000)]);
+
+    bytes[0x04..0x08].copy_from_slice(&28u32.to_le_bytes());
+    let header = MulHeader::parse(&bytes).unwrap();
+    assert_eq!(header.ads_loop(), Some((28, 5600)));
+    assert!(MulHeader::parse(&bytes[..0x7FF]).is_none());
+}
+
+#[test]
+fn damaged_chunks() {
+    let bytes = [7, 0, 0, 0, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3];
+    let mut body = &bytes[..];
+    let (chunk, header) = Chunk::parse(&mut body).unwrap();
+    assert_eq!(header, [0; 8]);
+    assert!(matches!(
+        chunk,
+        Chunk::Unknown {
+            variant: 7,
+            size: 12
+        }
+    ));
+    assert_eq!(get_bytes(&mut body, chunk.size() as usize), [1, 2, 3]);
+    assert!(body.is_empty());
+
+    let mut body = &bytes[..6];
+    assert!(Chunk::parse(&mut body).is_none());
+}
+
+#[test]
+fn codec_detection() {
+    let vag = [[0x12, 0x02].as_slice(), &[0x5a; 14]].concat().repeat(8);
+    assert_eq!(Codec::detect(&vag), Some(Codec::Vag));
+    let pcm = (0..256i16)
+        .flat_map(|i| (i * 100 - 12800).to_le_bytes())
+        .collect::<Vec<_>>();
+    assert_eq!(Codec::detect(&pcm), None);
+    let ima = [[0x34, 0x92, 40, 0].as_slice(), &[0x87; 32]]
+        .concat()
+        .repeat(4);
+    assert_eq!(Codec::detect(&ima), Some(Codec::Ima));
+    assert_eq!(Codec::Ima.decode(&ima).len(), 256);
+
+    let ms = [
+        [2, 0x30, 0x01, 0x10, 0x00, 0xF0, 0xFF].as_slice(),
+        &[0x87; 505],
+    ]
+    .concat()
+    .repeat(8);
+    assert_eq!(Codec::detect(&ms), None);
+    assert_eq!(ms_adpcm_block_size(&ms), Some(512));
+    assert_eq!(ms_adpcm_block_size(&pcm), None);
+    assert_eq!(ms_adpcm_block_size(&[0; 0x2000]), None);
+}
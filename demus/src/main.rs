@@ -3,6 +3,7 @@
 use std::{fs::File, io::Write, path::PathBuf};
 
 use clap::Parser;
+use loopexport::{flag_loop, LoopFormat, LoopPoints};
 
 const HEADER_VERSION_114: i32 = 270;
 const HEADER_VERSION_120: i32 = 276;
@@ -14,6 +15,47 @@ pub enum Platform {
     PC,
 }
 
+impl Platform {
+    /// Guesses the platform from the samples, which are console ones if most of them look like
+    /// ADPCM.
+    fn detect(sam_file: &[u8], wave_entries: &[WaveEntry]) -> Self {
+        let adpcm_waves = wave_entries
+            .iter()
+            .filter(|wave_entry| {
+                let start = wave_entry.offset as usize;
+                sam_file
+                    .get(start..start + wave_entry.size as usize)
+                    .is_some_and(|wave| start.is_multiple_of(16) && looks_like_adpcm(wave))
+            })
+            .count();
+        if adpcm_waves * 2 > wave_entries.len() {
+            Self::Console
+        } else {
+            Self::PC
+        }
+    }
+}
+
+/// Whether nearly every frame of a wave has a valid shift, filter and flag byte, with an end flag
+/// in its last frame.
+///
+/// Some console waves end with a frame of `0x77` nibbles and no flag instead, which gets fixed when
+/// writing them.
+fn looks_like_adpcm(wave: &[u8]) -> bool {
+    if wave.is_empty() || !wave.len().is_multiple_of(16) {
+        return false;
+    }
+    let frames = wave.chunks_exact(16);
+    let frame_count = frames.len();
+    let valid_frames = frames
+        .clone()
+        .filter(|frame| frame[0] & 0xf <= 12 && frame[0] >> 4 <= 4 && frame[1] <= 7)
+        .count();
+    let last = frames.last().unwrap();
+    let ended = last[1] & 1 != 0 || (last[0] == 0x07 && last[2..].iter().all(|&byte| byte == 0x77));
+    ended && valid_frames * 20 >= frame_count * 19
+}
+
 #[derive(Parser)]
 #[command(version)]
 struct Args {
@@ -21,15 +63,18 @@ struct Args {
     mus_path: PathBuf,
     /// `sam` file to read
     sam_path: PathBuf,
-    /// Tells program to use PC format. This is the default.
-    #[clap(long, short)]
+    /// Tells program to use PC format, instead of detecting it from the samples.
+    #[clap(long, short, conflicts_with = "console")]
     pc: bool,
-    /// Tells program to use console format.
+    /// Tells program to use console format, instead of detecting it from the samples.
     #[clap(long, short)]
     console: bool,
     /// Output folder of the files, defaults to the input with a different extension.
     #[clap(long, short)]
     output: Option<PathBuf>,
+    /// The formats to export the sample loop info in.
+    #[clap(long, value_delimiter = ',', default_value = "txt")]
+    loop_format: Vec<LoopFormat>,
 }
 
 fn secs_to_timecent(seconds: f32) -> i32 {
@@ -124,11 +169,6 @@ fn name_to_str(name: &[char; 20]) -> String {
 
 fn main() {
     let args = Args::parse();
-    let platform = if args.console {
-        Platform::Console
-    } else {
-        Platform::PC
-    };
 
     let mus_file = std::fs::read(&args.mus_path).unwrap();
     let mut mus_bytes = mus_file.iter().copied();
@@ -170,6 +210,18 @@ fn main() {
     #[cfg(debug_assertions)]
     dbg!(&wave_entries);
 
+    let detected = Platform::detect(&sam_file, &wave_entries);
+    let platform = if args.console {
+        Platform::Console
+    } else if args.pc {
+        Platform::PC
+    } else {
+        detected
+    };
+    if platform != detected {
+        eprintln!("The samples look like they come from the {detected:?} format instead");
+    }
+
     let mut program_entries = Vec::with_capacity(header.num_programs as usize);
     let mut program_zones = Vec::with_capacity(header.num_programs as usize);
     for _ in 0..header.num_programs {
@@ -315,29 +367,68 @@ fn main() {
         sample_file.write_all(&sam_file[wave.clone()]).unwrap();
     }
 
-    let smp_loop_info_path = args.mus_path.with_extension("").join(format!(
-        "{}_smploopinfo.txt",
+    let loops = waves
+        .iter()
+        .zip(&wave_entries)
+        .filter_map(|(wave, entry)| {
+            let file = format!("{}.ads", name_to_str(&entry.name));
+            let mus_loop = if entry.loop_info == 0 {
+                None
+            } else {
+                // The loop end is the first sample after the loop
+                let end = u32::try_from(entry.loop_end)
+                    .ok()
+                    .and_then(|end| end.checked_sub(1));
+                let points = u32::try_from(entry.loop_begin)
+                    .ok()
+                    .zip(end)
+                    .filter(|(start, end)| start <= end);
+                if points.is_none() {
+                    eprintln!(
+                        "{file}: invalid loop {} to {} in the MUS file, left out",
+                        entry.loop_begin, entry.loop_end
+                    );
+                }
+                points
+            };
+            let (start, end) = match platform {
+                Platform::PC => mus_loop?,
+                // The loop points of the MUS file don't match the samples on console, so the
+                // flags of the ADPCM frames are used instead
+                Platform::Console => {
+                    let wave_loop = flag_loop(&sam_file[wave.clone()]);
+                    if wave_loop != mus_loop {
+                        let describe = |points: Option<(u32, u32)>| match points {
+                            Some((start, end)) => format!("{start} to {end}"),
+                            None => "no loop".to_owned(),
+                        };
+                        eprintln!(
+                            "{file}: {} from the ADPCM flags, {} in the MUS file",
+                            describe(wave_loop),
+                            describe(mus_loop)
+                        );
+                    }
+                    wave_loop?
+                }
+            };
+            Some(LoopPoints::new(file, start, end))
+        })
+        .collect::<Vec<_>>();
+    let smp_loop_info_name = format!(
+        "{}_smploopinfo",
         args.mus_path
             .with_extension("")
             .file_stem()
             .unwrap()
             .to_string_lossy()
-    ));
-    let mut smp_loop_info = File::create(smp_loop_info_path).unwrap();
-    for entry in &wave_entries {
-        if entry.loop_info != 0 {
-            smp_loop_info
-                .write_all(
-                    format!(
-                        "{} {} {}.wav\r\n",
-                        entry.loop_begin,
-                        entry.loop_end - 1,
-                        name_to_str(&entry.name),
-                    )
-                    .as_bytes(),
-                )
-                .unwrap();
-        }
+    );
+    for format in &args.loop_format {
+        // The files for each sample go next to the samples
+        let dir = match format {
+            LoopFormat::Txt | LoopFormat::Json => args.mus_path.with_extension(""),
+            LoopFormat::Txtp | LoopFormat::Pos | LoopFormat::Tags => samples_dir.clone(),
+        };
+        loopexport::export(&loops, *format, &dir, &smp_loop_info_name).unwrap();
     }
 
     let info_path = args.mus_path.with_extension("").join(format!(
@@ -851,3 +942,33 @@ struct PresetEntry {
     midi_preset_number: i32,
     num_zones: i32,
 }
+
+#[test]
+fn platform_detection() {
+    let wave = |offset: i32, size: i32| WaveEntry {
+        name: ['\0'; 20],
+        offset,
+        loop_begin: 0,
+        size,
+        loop_end: 0,
+        sample_rate: 22050,
+        original_pitch: 0,
+        loop_info: 0,
+        snd_handle: 0,
+    };
+    let mut sam_file = [[0x12, 0x02].as_slice(), &[0x5a; 14]].concat().repeat(8);
+    sam_file[16 * 3 + 1] = 0x01;
+    sam_file[16 * 7..].copy_from_slice(
+        &[0x07, 0x00]
+            .into_iter()
+            .chain([0x77; 14])
+            .collect::<Vec<_>>(),
+    );
+    let waves = [wave(0, 64), wave(64, 64)];
+    assert_eq!(Platform::detect(&sam_file, &waves), Platform::Console);
+
+    let pcm = (0..64i16)
+        .flat_map(|i| (i * 300 - 9600).to_le_bytes())
+        .collect::<Vec<_>>();
+    assert_eq!(Platform::detect(&pcm, &[wave(0, 128)]), Platform::PC);
+}
@@ -28,6 +28,29 @@ struct Args {
     /// Folder to put output files in.
     #[clap(short, long)]
     output: Option<PathBuf>,
+    /// What version the `vh` file is written as, all of them sharing the same layout.
+    #[clap(long, default_value = "7")]
+    vab_version: VabVersion,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum VabVersion {
+    #[value(name = "5")]
+    V5,
+    #[value(name = "6")]
+    V6,
+    #[value(name = "7")]
+    V7,
+}
+
+impl VabVersion {
+    fn number(self) -> u32 {
+        match self {
+            Self::V5 => 5,
+            Self::V6 => 6,
+            Self::V7 => 7,
+        }
+    }
 }
 
 fn four_bytes(bytes: &mut Iter<u8>) -> [u8; 4] {
@@ -132,7 +155,7 @@ fn main() {
         .write_all(
             &[
                 [0x70, 0x42, 0x41, 0x56],
-                [7, 0, 0, 0],
+                args.vab_version.number().to_le_bytes(),
                 [0; 4],
                 (32 + 2048
                     + snd_file.header.num_programs * 512
@@ -186,11 +209,7 @@ fn main() {
             .unwrap();
     }
     vh_output
-        .write_all(
-            &std::iter::repeat(0)
-                .take(16 * (128 - snd_file.header.num_programs as usize))
-                .collect::<Vec<_>>(),
-        )
+        .write_all(&vec![0; 16 * (128 - snd_file.header.num_programs as usize)])
         .unwrap();
 
     let vb_output_path = output_folder.join(
@@ -241,11 +260,7 @@ fn main() {
                 .unwrap();
         }
         vh_output
-            .write_all(
-                &std::iter::repeat(0)
-                    .take(32 * (16 - program.num_zones as usize))
-                    .collect::<Vec<_>>(),
-            )
+            .write_all(&vec![0; 32 * (16 - program.num_zones as usize)])
             .unwrap();
     }
     vh_output.write_all(&[0; 2]).unwrap();
@@ -256,11 +271,7 @@ fn main() {
         vh_output.write_all(&size).unwrap();
     }
     vh_output
-        .write_all(
-            &std::iter::repeat(0)
-                .take(512 - (1 + smp_file.waves.len()) * 2)
-                .collect::<Vec<_>>(),
-        )
+        .write_all(&vec![0; 512 - (1 + smp_file.waves.len()) * 2])
         .unwrap();
 
     println!("SND header");
@@ -288,6 +299,7 @@ fn main() {
     println!("Instruments: {}", snd_file.header.num_programs);
     println!("Zones: {}", snd_file.header.num_zones);
     println!("Samples: {}", snd_file.header.num_waves);
+    println!("VAB version: {}", args.vab_version.number());
     println!("Sequences: {}", snd_file.header.num_sequences);
     println!("Labels: {}", snd_file.header.num_labels);
 }
@@ -0,0 +1,443 @@
+use std::{fmt::Display, fs::File, io::Write, path::PathBuf};
+
+use clap::{Parser, ValueEnum};
+
+const VAGP_MAGIC_NUMBER: [u8; 4] = [0x56, 0x41, 0x47, 0x70];
+const VAGI_MAGIC_NUMBER: [u8; 4] = [0x56, 0x41, 0x47, 0x69];
+const ADS_MAGIC_NUMBER: [u8; 4] = [0x53, 0x53, 0x68, 0x64];
+const VAB_MAGIC_NUMBER: [u8; 4] = [0x70, 0x42, 0x41, 0x56];
+const SEQ_MAGIC_NUMBER: [u8; 4] = [0x70, 0x51, 0x45, 0x53];
+/// Where the audio of a `VAGi` file starts.
+const VAGI_HEADER_SIZE: usize = 0x800;
+
+#[derive(Parser)]
+#[clap(version)]
+struct Args {
+    input: PathBuf,
+    /// Writes the body without its header.
+    #[clap(short, long, conflicts_with = "convert")]
+    strip: bool,
+    /// Writes the body with a header of another type.
+    #[clap(short, long)]
+    convert: Option<Format>,
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Ads,
+    Vag,
+}
+
+enum Header {
+    Vag {
+        version: u32,
+        /// The interleave of `VAGi` files, which are the only ones with several channels.
+        interleave: Option<u32>,
+        /// The size of the data of each channel.
+        data_size: u32,
+        sample_rate: u32,
+        channels: u8,
+        name: String,
+        header_size: usize,
+    },
+    Ads {
+        codec: u32,
+        sample_rate: u32,
+        channels: u32,
+        interleave: u32,
+        loop_start: u32,
+        loop_end: u32,
+        body_size: u32,
+    },
+    Vab {
+        version: u32,
+        vab_id: u32,
+        total_size: u32,
+        programs_number: u16,
+        tones_number: u16,
+        vags_number: u16,
+        master_volume: u8,
+        master_pan: u8,
+        bank_attributes_1: u8,
+        bank_attributes_2: u8,
+    },
+    Seq {
+        version: u32,
+        /// Ticks per quarter note.
+        resolution: u16,
+        /// Microseconds per quarter note.
+        tempo: u32,
+        time_signature: (u8, u8),
+    },
+}
+
+impl Header {
+    fn parse(file: &[u8]) -> Result<Self, String> {
+        let le = |offset: usize| load_bytes(&file[offset..]);
+        let be = |offset: usize| {
+            u32::from_be_bytes([
+                file[offset],
+                file[offset + 1],
+                file[offset + 2],
+                file[offset + 3],
+            ])
+        };
+        let u16_le = |offset: usize| u16::from_le_bytes([file[offset], file[offset + 1]]);
+
+        match file.get(0..4) {
+            Some(magic) if magic == VAGP_MAGIC_NUMBER && file.len() >= 48 => {
+                let data_size = be(0x0C);
+                Ok(Self::Vag {
+                    version: be(0x04),
+                    interleave: None,
+                    data_size,
+                    sample_rate: be(0x10),
+                    channels: 1,
+                    name: name(&file[0x20..0x30]),
+                    header_size: if data_size as usize + 64 == file.len() {
+                        64
+                    } else {
+                        48
+                    },
+                })
+            }
+            Some(magic) if magic == VAGI_MAGIC_NUMBER && file.len() >= VAGI_HEADER_SIZE => {
+                Ok(Self::Vag {
+                    version: be(0x04),
+                    interleave: Some(le(0x08)),
+                    data_size: be(0x0C),
+                    sample_rate: be(0x10),
+                    channels: file[0x1E].max(2),
+                    name: name(&file[0x20..0x30]),
+                    header_size: VAGI_HEADER_SIZE,
+                })
+            }
+            Some(magic) if magic == ADS_MAGIC_NUMBER && file.len() >= 0x28 => Ok(Self::Ads {
+                codec: le(0x08),
+                sample_rate: le(0x0C),
+                channels: le(0x10),
+                interleave: le(0x14),
+                loop_start: le(0x18),
+                loop_end: le(0x1C),
+                body_size: le(0x24),
+            }),
+            Some(magic) if magic == VAB_MAGIC_NUMBER && file.len() >= 0x20 => Ok(Self::Vab {
+                version: le(0x04),
+                vab_id: le(0x08),
+                total_size: le(0x0C),
+                programs_number: u16_le(0x12),
+                tones_number: u16_le(0x14),
+                vags_number: u16_le(0x16),
+                master_volume: file[0x18],
+                master_pan: file[0x19],
+                bank_attributes_1: file[0x1A],
+                bank_attributes_2: file[0x1B],
+            }),
+            Some(magic) if magic == SEQ_MAGIC_NUMBER && file.len() >= 15 => Ok(Self::Seq {
+                version: be(0x04),
+                resolution: u16::from_be_bytes([file[8], file[9]]),
+                tempo: u32::from_be_bytes([0, file[10], file[11], file[12]]),
+                time_signature: (file[13], file[14]),
+            }),
+            _ => Err("Expected a VAGp, VAGi, SShd, pBAV or pQES header".to_owned()),
+        }
+    }
+
+    /// The audio after the header, if this is the header of an audio stream.
+    fn body<'a>(&self, file: &'a [u8]) -> Option<&'a [u8]> {
+        let (start, size) = match self {
+            Self::Vag {
+                data_size,
+                channels,
+                header_size,
+                ..
+            } => (*header_size, *data_size as usize * *channels as usize),
+            Self::Ads { body_size, .. } => (0x28, *body_size as usize),
+            Self::Vab { .. } | Self::Seq { .. } => return None,
+        };
+        if start + size != file.len() {
+            eprintln!(
+                "The header states {size} bytes of audio, but the file has {}",
+                file.len() - start
+            );
+        }
+        Some(&file[start..(start + size).min(file.len())])
+    }
+
+    /// The same audio with a header of another type.
+    fn convert(&self, body: &[u8], format: Format) -> Result<Vec<u8>, String> {
+        match (self, format) {
+            (
+                Self::Vag {
+                    interleave,
+                    sample_rate,
+                    channels,
+                    ..
+                },
+                Format::Ads,
+            ) => {
+                let channels = u32::from(*channels);
+                let interleave = interleave.unwrap_or_default();
+                Ok([
+                    ADS_MAGIC_NUMBER,
+                    [0x18, 0, 0, 0],
+                    [0x10, 0, 0, 0],
+                    sample_rate.to_le_bytes(),
+                    channels.to_le_bytes(),
+                    interleave.to_le_bytes(),
+                    [0xff; 4],
+                    [0xff; 4],
+                    [0x53, 0x53, 0x62, 0x64],
+                    (body.len() as u32).to_le_bytes(),
+                ]
+                .into_iter()
+                .flatten()
+                .chain(body.iter().copied())
+                .collect())
+            }
+            (
+                Self::Ads {
+                    codec,
+                    sample_rate,
+                    channels,
+                    interleave,
+                    ..
+                },
+                Format::Vag,
+            ) => {
+                if *codec != 0x10 {
+                    return Err(
+                        "Only SONY_4BIT_ADPCM streams can be written as VAG files".to_owned()
+                    );
+                }
+                let channels = (*channels).max(1);
+                let (magic, interleave_bytes, channel_byte) = match channels {
+                    1 => (VAGP_MAGIC_NUMBER, [0; 4], 0),
+                    _ => (
+                        VAGI_MAGIC_NUMBER,
+                        interleave.to_le_bytes(),
+                        u8::try_from(channels).map_err(|_| "Too many channels for a VAG file")?,
+                    ),
+                };
+                let mut file = [
+                    magic,
+                    [0, 0, 0, 3],
+                    interleave_bytes,
+                    (body.len() as u32 / channels).to_be_bytes(),
+                    sample_rate.to_be_bytes(),
+                    [0; 4],
+                    [0; 4],
+                    [0, 0, channel_byte, 0],
+                    [0; 4],
+                    [0; 4],
+                    [0; 4],
+                    [0; 4],
+                ]
+                .concat();
+                if channels > 1 {
+                    file.resize(VAGI_HEADER_SIZE, 0);
+                }
+                file.extend_from_slice(body);
+                Ok(file)
+            }
+            (Self::Vag { .. }, Format::Vag) | (Self::Ads { .. }, Format::Ads) => {
+                Err("The file already has this type of header".to_owned())
+            }
+            (Self::Vab { .. } | Self::Seq { .. }, _) => {
+                Err("Only VAG and ADS headers can be converted".to_owned())
+            }
+        }
+    }
+}
+
+impl Display for Header {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Vag {
+                version,
+                interleave,
+                data_size,
+                sample_rate,
+                channels,
+                name,
+                header_size,
+            } => {
+                match interleave {
+                    Some(interleave) => {
+                        writeln!(f, "Type: VAGi (interleaved VAG)")?;
+                        writeln!(f, "Interleave: {interleave:#x}")?;
+                    }
+                    None => writeln!(f, "Type: VAGp (VAG)")?,
+                }
+                writeln!(f, "Version: {version:#x}")?;
+                writeln!(f, "Data size: {data_size} bytes per channel")?;
+                writeln!(f, "Samplerate: {sample_rate}")?;
+                writeln!(f, "Channels: {channels}")?;
+                writeln!(f, "Name: {name}")?;
+                write!(f, "Header size: {header_size} bytes")
+            }
+            Self::Ads {
+                codec,
+                sample_rate,
+                channels,
+                interleave,
+                loop_start,
+                loop_end,
+                body_size,
+            } => {
+                let codec_name = match codec {
+                    0x01 => "PCM16LE",
+                    0x10 => "SONY_4BIT_ADPCM",
+                    _ => "unknown",
+                };
+                writeln!(f, "Type: SShd (ADS)")?;
+                writeln!(f, "Codec: {codec:#x} ({codec_name})")?;
+                writeln!(f, "Samplerate: {sample_rate}")?;
+                writeln!(f, "Channels: {channels}")?;
+                writeln!(f, "Interleave: {interleave:#x}")?;
+                match (*loop_start, *loop_end) {
+                    (u32::MAX, u32::MAX) => writeln!(f, "Loop: none")?,
+                    _ => writeln!(f, "Loop: {loop_start} to {loop_end}")?,
+                }
+                write!(f, "Body size: {body_size} bytes")
+            }
+            Self::Vab {
+                version,
+                vab_id,
+                total_size,
+                programs_number,
+                tones_number,
+                vags_number,
+                master_volume,
+                master_pan,
+                bank_attributes_1,
+                bank_attributes_2,
+            } => {
+                writeln!(f, "Type: pBAV (VAB)")?;
+                writeln!(f, "Version: {version}")?;
+                writeln!(f, "VAB ID: {vab_id}")?;
+                writeln!(f, "Total size: {total_size} bytes")?;
+                writeln!(f, "Programs: {programs_number}")?;
+                writeln!(f, "Tones: {tones_number}")?;
+                writeln!(f, "VAGs: {vags_number}")?;
+                writeln!(f, "Master volume: {master_volume}")?;
+                writeln!(f, "Master pan: {master_pan}")?;
+                write!(
+                    f,
+                    "Bank attributes: {bank_attributes_1:#04x} {bank_attributes_2:#04x}"
+                )
+            }
+            Self::Seq {
+                version,
+                resolution,
+                tempo,
+                time_signature: (numerator, denominator),
+            } => {
+                writeln!(f, "Type: pQES (SEQ)")?;
+                writeln!(f, "Version: {version}")?;
+                writeln!(f, "Resolution: {resolution} ticks per quarter note")?;
+                writeln!(
+                    f,
+                    "Tempo: {tempo} microseconds per quarter note ({:.2} BPM)",
+                    60_000_000.0 / f64::from((*tempo).max(1))
+                )?;
+                write!(
+                    f,
+                    "Time signature: {numerator}/{}",
+                    1u32.checked_shl(u32::from(*denominator)).unwrap_or(0)
+                )
+            }
+        }
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let file = std::fs::read(&args.input).unwrap();
+    let header = match Header::parse(&file) {
+        Ok(header) => header,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+    println!("{header}");
+
+    if !args.strip && args.convert.is_none() {
+        return;
+    }
+    let Some(body) = header.body(&file) else {
+        eprintln!("Only VAG and ADS headers can be stripped or converted");
+        std::process::exit(1);
+    };
+    let (bytes, extension) = match args.convert {
+        Some(format) => match header.convert(body, format) {
+            Ok(bytes) => (bytes, format!("{format:?}").to_lowercase()),
+            Err(err) => {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+        },
+        None => (body.to_vec(), "bin".to_owned()),
+    };
+
+    let mut output = File::create(
+        args.output
+            .unwrap_or_else(|| args.input.with_extension(extension)),
+    )
+    .unwrap();
+    output.write_all(&bytes).unwrap();
+}
+
+/// A name padded with zeros.
+fn name(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .trim_end_matches('\0')
+        .to_owned()
+}
+
+fn load_bytes(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[test]
+fn conversion() {
+    let ads = [
+        ADS_MAGIC_NUMBER,
+        [0x18, 0, 0, 0],
+        [0x10, 0, 0, 0],
+        22050u32.to_le_bytes(),
+        2u32.to_le_bytes(),
+        0x10u32.to_le_bytes(),
+        [0xff; 4],
+        [0xff; 4],
+        [0x53, 0x53, 0x62, 0x64],
+        64u32.to_le_bytes(),
+        [1; 4],
+    ]
+    .concat()
+    .into_iter()
+    .chain([2; 60])
+    .collect::<Vec<_>>();
+    let header = Header::parse(&ads).unwrap();
+    let body = header.body(&ads).unwrap();
+    assert_eq!(body.len(), 64);
+
+    let vag = header.convert(body, Format::Vag).unwrap();
+    assert_eq!(vag.len(), VAGI_HEADER_SIZE + 64);
+    let header = Header::parse(&vag).unwrap();
+    assert!(matches!(
+        header,
+        Header::Vag {
+            interleave: Some(0x10),
+            data_size: 32,
+            channels: 2,
+            ..
+        }
+    ));
+    let body = header.body(&vag).unwrap();
+    assert_eq!(header.convert(body, Format::Ads).unwrap(), ads);
+}
@@ -0,0 +1,170 @@
+//! Finds sample loop points in SPU ADPCM flags and writes them in the formats read by other tools.
+
+use std::{io, path::Path};
+
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum LoopFormat {
+    /// A LoopingAudioConverter `loop.txt` for every sample.
+    Txt,
+    /// A vgmstream `.txtp` file per sample.
+    Txtp,
+    /// A `.pos` file per sample, with the loop start and end as 32-bit integers.
+    Pos,
+    /// A `LOOPSTART`/`LOOPLENGTH` tag file per sample, for Ogg and FLAC taggers.
+    Tags,
+    /// A JSON manifest for every sample.
+    Json,
+}
+
+/// The loop of a sample, in samples.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct LoopPoints {
+    /// The name of the audio file, with its extension.
+    pub file: String,
+    pub start: u32,
+    /// The last sample played before going back to the start.
+    pub end: u32,
+}
+
+impl LoopPoints {
+    pub fn new(file: impl Into<String>, start: u32, end: u32) -> Self {
+        Self {
+            file: file.into(),
+            start,
+            end,
+        }
+    }
+
+    /// The name of the file without its extension.
+    pub fn stem(&self) -> &str {
+        self.file
+            .rsplit_once('.')
+            .map_or(self.file.as_str(), |(stem, _)| stem)
+    }
+
+    /// The amount of samples in the loop, or 0 if it ends before it starts.
+    pub fn length(&self) -> u32 {
+        self.end
+            .checked_sub(self.start)
+            .map_or(0, |length| length.saturating_add(1))
+    }
+
+    /// A line of a LoopingAudioConverter `loop.txt`, which refers to the sample converted to WAV.
+    pub fn loop_txt(&self) -> String {
+        format!("{} {} {}.wav\r\n", self.start, self.end, self.stem())
+    }
+
+    /// A vgmstream `.txtp` file, playing the sample with the loop end being the first sample not
+    /// played.
+    pub fn txtp(&self) -> String {
+        format!("{} #I {} {}\r\n", self.file, self.start, self.end + 1)
+    }
+
+    pub fn pos(&self) -> [u8; 8] {
+        let mut bytes = [0; 8];
+        bytes[0..4].copy_from_slice(&self.start.to_le_bytes());
+        bytes[4..8].copy_from_slice(&(self.end + 1).to_le_bytes());
+        bytes
+    }
+
+    /// Vorbis comments, as read by `metaflac --import-tags-from` and `vorbiscomment -c`.
+    pub fn tags(&self) -> String {
+        format!("LOOPSTART={}\nLOOPLENGTH={}\n", self.start, self.length())
+    }
+}
+
+#[derive(Serialize)]
+struct ManifestEntry<'a> {
+    #[serde(flatten)]
+    points: &'a LoopPoints,
+    length: u32,
+}
+
+pub fn json(loops: &[LoopPoints]) -> String {
+    serde_json::to_string_pretty(
+        &loops
+            .iter()
+            .map(|points| ManifestEntry {
+                points,
+                length: points.length(),
+            })
+            .collect::<Vec<_>>(),
+    )
+    .unwrap()
+}
+
+/// Writes the loops in `dir`, with `list_name` being the name of the `txt` and `json` files
+/// without their extension.
+pub fn export(
+    loops: &[LoopPoints],
+    format: LoopFormat,
+    dir: &Path,
+    list_name: &str,
+) -> io::Result<()> {
+    match format {
+        LoopFormat::Txt => std::fs::write(
+            dir.join(format!("{list_name}.txt")),
+            loops.iter().map(LoopPoints::loop_txt).collect::<String>(),
+        ),
+        LoopFormat::Json => std::fs::write(dir.join(format!("{list_name}.json")), json(loops)),
+        LoopFormat::Txtp => loops.iter().try_for_each(|points| {
+            std::fs::write(dir.join(format!("{}.txtp", points.stem())), points.txtp())
+        }),
+        LoopFormat::Pos => loops.iter().try_for_each(|points| {
+            std::fs::write(dir.join(format!("{}.pos", points.stem())), points.pos())
+        }),
+        LoopFormat::Tags => loops.iter().try_for_each(|points| {
+            std::fs::write(dir.join(format!("{}.tags", points.stem())), points.tags())
+        }),
+    }
+}
+
+/// Finds the loop of a stream of SPU ADPCM frames from their flags, in samples.
+///
+/// The loop ends on the first frame with the end flag, as long as it also has the repeat flag, and
+/// starts on the last frame up to it with the start flag, which can be the end frame itself. If
+/// there is no end flag, a loop start makes the stream loop until its end.
+pub fn flag_loop(frames: &[u8]) -> Option<(u32, u32)> {
+    let frames = frames.chunks_exact(16).collect::<Vec<_>>();
+    let end = frames
+        .iter()
+        .position(|frame| frame[1] <= 7 && frame[1] & 1 != 0);
+    let start = frames[..end.map_or(frames.len(), |end| end + 1)]
+        .iter()
+        .rposition(|frame| frame[1] <= 7 && frame[1] & 4 != 0);
+
+    let (start, end) = match (start, end) {
+        (Some(start), Some(end)) if frames[end][1] & 2 != 0 => (start, end),
+        (Some(start), None) if frames[start][1] == 6 => (start, frames.len() - 1),
+        _ => return None,
+    };
+    Some((start as u32 * 28, (end as u32 + 1) * 28 - 1))
+}
+
+#[test]
+fn formats() {
+    let points = LoopPoints::new("synth.ads", 3164, 11788);
+    assert_eq!(points.loop_txt(), "3164 11788 synth.wav\r\n");
+    assert_eq!(points.txtp(), "synth.ads #I 3164 11789\r\n");
+    assert_eq!(points.pos()[4..], 11789u32.to_le_bytes());
+    assert_eq!(points.tags(), "LOOPSTART=3164\nLOOPLENGTH=8625\n");
+    assert!(json(&[points]).contains("\"length\": 8625"));
+    assert_eq!(LoopPoints::new("synth.ads", 100, 50).length(), 0);
+}
+
+#[test]
+fn flag_loops() {
+    let mut frames = vec![0; 16 * 6];
+    frames[16 + 1] = 6;
+    frames[16 * 2 + 1] = 2;
+    frames[16 * 4 + 1] = 3;
+    assert_eq!(flag_loop(&frames), Some((28, 139)));
+    frames[16 * 4 + 1] = 1;
+    assert_eq!(flag_loop(&frames), None);
+    frames[16 * 4 + 1] = 2;
+    assert_eq!(flag_loop(&frames), Some((28, 6 * 28 - 1)));
+    frames[16 * 3 + 1] = 7;
+    assert_eq!(flag_loop(&frames), Some((3 * 28, 4 * 28 - 1)));
+}
@@ -0,0 +1,448 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use clap::Parser;
+use serde::Deserialize;
+
+const HEADER_SIZE: usize = 0x800;
+const ADS_MAGIC_NUMBER: [u8; 4] = [0x53, 0x53, 0x68, 0x64];
+const VAG_MAGIC_NUMBER: [u8; 4] = [0x56, 0x41, 0x47, 0x70];
+
+#[derive(Parser)]
+#[clap(version)]
+struct Args {
+    /// The original `mul` file, or the `csv` or `json` timeline `demul` wrote for it.
+    layout: PathBuf,
+    /// The mono audio channels, in order.
+    #[clap(required = true)]
+    channels: Vec<PathBuf>,
+    /// The samplerate written in the header when rebuilding from a timeline without the header
+    /// file `demul` wrote next to it.
+    #[clap(short = 'r', long)]
+    sample_rate: Option<u32>,
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+}
+
+/// A chunk of the rebuilt file, with its contents unless it holds audio.
+struct Chunk {
+    variant: u32,
+    /// The bytes between the size and the contents: 8 for every chunk, plus the 16-byte audio
+    /// header for audio chunks.
+    header: Vec<u8>,
+    /// The size of the audio in audio chunks, which is 16 bytes less than what gets written.
+    size: u32,
+    contents: Option<Vec<u8>>,
+}
+
+#[derive(Deserialize)]
+struct TimelineEntry {
+    #[serde(rename = "type")]
+    variant: u32,
+    size: u32,
+    /// The bytes between the size and the contents in hex, if the timeline has them.
+    header: Option<String>,
+    file: Option<String>,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    if let Err(err) = run(args) {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let channels = args
+        .channels
+        .iter()
+        .map(|path| read_channel(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let channel_count = channels.len() as u32;
+
+    let is_mul = args
+        .layout
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("mul"));
+    let stem = args.layout.file_stem().unwrap().to_string_lossy();
+    let stem = stem.strip_suffix("_timeline").unwrap_or(&stem);
+    // The header `demul` saved next to the timeline
+    let header_file = args.layout.with_file_name(format!("{stem}_header.bin"));
+    let (mut header, layout) = if is_mul {
+        let file = std::fs::read(&args.layout).map_err(|err| err.to_string())?;
+        (
+            original_header(&file, channel_count)?,
+            parse_mul(&file[HEADER_SIZE..])?,
+        )
+    } else if header_file.exists() {
+        let file = std::fs::read(&header_file).map_err(|err| err.to_string())?;
+        (
+            original_header(&file, channel_count)?,
+            parse_timeline(&args.layout)?,
+        )
+    } else {
+        let sample_rate = args.sample_rate.ok_or(format!(
+            "A samplerate is needed to rebuild from a timeline without {}",
+            header_file.to_string_lossy()
+        ))?;
+        let mut header = vec![0; HEADER_SIZE];
+        header[0..4].copy_from_slice(&sample_rate.to_le_bytes());
+        header[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        header[12..16].copy_from_slice(&channel_count.to_le_bytes());
+        (header, parse_timeline(&args.layout)?)
+    };
+    let made_up_header = !is_mul && !header_file.exists();
+
+    let (body, channel_size) = multiplex(&layout, &channels);
+    if made_up_header {
+        let sample_count = match channels.iter().all(|(_, pcm)| *pcm) {
+            true => channel_size / 2,
+            false => channel_size / 16 * 28,
+        };
+        header[8..12].copy_from_slice(&(sample_count as u32).to_le_bytes());
+    }
+
+    let output = args
+        .output
+        .unwrap_or_else(|| args.layout.with_file_name(format!("{stem}_rebuilt.mul")));
+    let mut out = File::create(output).unwrap();
+    out.write_all(&header).unwrap();
+    out.write_all(&body).unwrap();
+    Ok(())
+}
+
+/// Takes the header of an original MUL file, checking that it has as many channels as were given.
+fn original_header(file: &[u8], channel_count: u32) -> Result<Vec<u8>, String> {
+    let header = file
+        .get(..HEADER_SIZE)
+        .ok_or("The file is too small to hold a MUL header")?
+        .to_vec();
+    let original_channels = load_bytes(&header[12..]);
+    if original_channels != channel_count {
+        return Err(format!(
+            "The original file has {original_channels} channels, but {channel_count} were given"
+        ));
+    }
+    Ok(header)
+}
+
+/// Reads the body of a mono stream, and whether it is PCM16 rather than ADPCM.
+///
+/// ADS, VAG and WAV files have their header removed, and any other file is taken as raw audio.
+fn read_channel(path: &Path) -> Result<(Vec<u8>, bool), String> {
+    let file = std::fs::read(path).map_err(|err| format!("{}: {err}", path.to_string_lossy()))?;
+    match file.get(0..4) {
+        Some(magic) if magic == ADS_MAGIC_NUMBER && file.len() >= 0x28 => {
+            if load_bytes(&file[0x10..]) > 1 {
+                return Err(format!(
+                    "{}: only mono ADS files can be used as channels",
+                    path.to_string_lossy()
+                ));
+            }
+            Ok((file[0x28..].to_vec(), load_bytes(&file[8..]) == 0x01))
+        }
+        Some(magic) if magic == VAG_MAGIC_NUMBER && file.len() >= 48 => {
+            let data_size = u32::from_be_bytes([file[12], file[13], file[14], file[15]]) as usize;
+            let header_size = if data_size + 64 == file.len() { 64 } else { 48 };
+            Ok((file[header_size..].to_vec(), false))
+        }
+        Some(b"RIFF") => {
+            // Looks for the data chunk after the RIFF header, checking the format before it
+            let mut offset = 12;
+            let mut mono_pcm16 = false;
+            while offset + 8 <= file.len() {
+                let size = load_bytes(&file[offset + 4..]) as usize;
+                if &file[offset..offset + 4] == b"fmt " {
+                    let format = file.get(offset + 8..offset + 24).unwrap_or_default();
+                    // The PCM format tag, 1 channel and 16 bits per sample
+                    mono_pcm16 = format.len() == 16
+                        && format[0..4] == [1, 0, 1, 0]
+                        && format[14..16] == [16, 0];
+                }
+                if &file[offset..offset + 4] == b"data" {
+                    if !mono_pcm16 {
+                        return Err(format!(
+                            "{}: only mono PCM16 WAV files can be used as channels",
+                            path.to_string_lossy()
+                        ));
+                    }
+                    let end = (offset + 8 + size).min(file.len());
+                    return Ok((file[offset + 8..end].to_vec(), true));
+                }
+                offset += 8 + size + size % 2;
+            }
+            Err(format!("{}: no data chunk found", path.to_string_lossy()))
+        }
+        _ => Ok((file, false)),
+    }
+}
+
+/// Takes the layout of the chunks after the header of an original MUL file.
+fn parse_mul(body: &[u8]) -> Result<Vec<Chunk>, String> {
+    let mut chunks = vec![];
+    let mut rest = body;
+    while !rest.is_empty() {
+        let offset = HEADER_SIZE + body.len() - rest.len();
+        let (variant, size) = match (rest.get(0..4), rest.get(4..8)) {
+            (Some(variant), Some(size)) => (load_bytes(variant), load_bytes(size)),
+            _ => return Err(format!("Chunk at {offset:#x} is truncated")),
+        };
+        let (header_size, size) = match variant {
+            0 => (8 + 16, size.saturating_sub(16)),
+            _ => (8, size),
+        };
+        let contents_start = 8 + header_size;
+        let end = contents_start + size as usize;
+        if end > rest.len() {
+            return Err(format!("Chunk at {offset:#x} is truncated"));
+        }
+        chunks.push(Chunk {
+            variant,
+            header: rest[8..contents_start].to_vec(),
+            size,
+            contents: (variant != 0).then(|| rest[contents_start..end].to_vec()),
+        });
+        rest = &rest[end..];
+    }
+    Ok(chunks)
+}
+
+/// Takes the layout of the chunks from a timeline, along with the files of the data chunks.
+fn parse_timeline(path: &Path) -> Result<Vec<Chunk>, String> {
+    let text = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let entries = if path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+    {
+        serde_json::from_str(&text).map_err(|err| err.to_string())?
+    } else {
+        parse_csv(&text)?
+    };
+
+    let dir = path.parent().unwrap_or(Path::new(""));
+    entries
+        .into_iter()
+        .map(|entry| {
+            let contents = match (entry.variant, entry.file) {
+                (0, _) => None,
+                (_, Some(file)) => {
+                    let contents =
+                        std::fs::read(dir.join(&file)).map_err(|err| format!("{file}: {err}"))?;
+                    if contents.len() != entry.size as usize {
+                        eprintln!(
+                            "{file} has {} bytes instead of the original {}",
+                            contents.len(),
+                            entry.size
+                        );
+                    }
+                    Some(contents)
+                }
+                (_, None) => Some(vec![0; entry.size as usize]),
+            };
+            let header_size = if entry.variant == 0 { 8 + 16 } else { 8 };
+            let header = match entry.header {
+                Some(header) => parse_hex(&header)
+                    .filter(|header| header.len() == header_size)
+                    .ok_or(format!("Invalid chunk header: {header}"))?,
+                // Timelines from before the headers were kept only have the audio size in them
+                None => {
+                    let mut header = vec![0; 8];
+                    if entry.variant == 0 {
+                        header.extend(entry.size.to_le_bytes());
+                        header.extend([0; 12]);
+                    }
+                    header
+                }
+            };
+            Ok(Chunk {
+                variant: entry.variant,
+                header,
+                size: entry.size,
+                contents,
+            })
+        })
+        .collect()
+}
+
+fn parse_csv(text: &str) -> Result<Vec<TimelineEntry>, String> {
+    let mut lines = text.lines();
+    let columns = lines
+        .next()
+        .unwrap_or_default()
+        .split(',')
+        .collect::<Vec<_>>();
+    let column = |name: &str| {
+        columns
+            .iter()
+            .position(|column| *column == name)
+            .ok_or(format!("The timeline has no {name} column"))
+    };
+    let (variant, size, file) = (column("type")?, column("size")?, column("file")?);
+    let header = column("header").ok();
+
+    lines
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let fields = line.split(',').collect::<Vec<_>>();
+            let number = |i: usize| {
+                fields
+                    .get(i)
+                    .and_then(|field| field.parse().ok())
+                    .ok_or(format!("Invalid timeline line: {line}"))
+            };
+            Ok(TimelineEntry {
+                variant: number(variant)?,
+                size: number(size)?,
+                header: header
+                    .and_then(|header| fields.get(header))
+                    .map(|header| header.to_string()),
+                file: fields
+                    .get(file)
+                    .filter(|file| !file.is_empty())
+                    .map(|file| file.to_string()),
+            })
+        })
+        .collect()
+}
+
+/// Writes the chunks, splitting the channels into the audio chunks.
+///
+/// Channels shorter than the original audio are padded with zeros, which are silence in both
+/// ADPCM and PCM16, and longer ones are cut. Returns the body and the size of each channel in it.
+fn multiplex(layout: &[Chunk], channels: &[(Vec<u8>, bool)]) -> (Vec<u8>, usize) {
+    let mut body = vec![];
+    let mut position = 0;
+    for chunk in layout {
+        body.extend(chunk.variant.to_le_bytes());
+        match &chunk.contents {
+            Some(contents) => {
+                body.extend((contents.len() as u32).to_le_bytes());
+                body.extend(&chunk.header);
+                body.extend(contents);
+            }
+            None => {
+                let split_size = (chunk.size / channels.len() as u32) as usize;
+                body.extend((chunk.size + 16).to_le_bytes());
+                body.extend(&chunk.header);
+                for (channel, _) in channels {
+                    let slice = channel
+                        .get(position..channel.len().min(position + split_size))
+                        .unwrap_or_default();
+                    body.extend(slice);
+                    body.resize(body.len() + split_size - slice.len(), 0);
+                }
+                // The bytes that don't split evenly into the channels are kept as silence, so
+                // that the chunk has the size its header says
+                body.resize(body.len() + chunk.size as usize % channels.len(), 0);
+                position += split_size;
+            }
+        }
+    }
+
+    for (i, (channel, _)) in channels.iter().enumerate() {
+        if channel.len() > position {
+            eprintln!(
+                "Channel {i} is {} bytes longer than the original audio, which were left out",
+                channel.len() - position
+            );
+        }
+    }
+    (body, position)
+}
+
+fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn load_bytes(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[test]
+fn multiplexing() {
+    let layout = [
+        Chunk {
+            variant: 0,
+            header: vec![0; 24],
+            size: 64,
+            contents: None,
+        },
+        Chunk {
+            variant: 1,
+            header: vec![0; 8],
+            size: 3,
+            contents: Some(vec![9; 3]),
+        },
+    ];
+    let channels = [(vec![1; 32], false), (vec![2; 16], false)];
+    let (body, channel_size) = multiplex(&layout, &channels);
+    assert_eq!(channel_size, 32);
+    assert_eq!(body[4..8], 80u32.to_le_bytes());
+    assert_eq!(body[32..64], [1; 32]);
+    assert_eq!(body[64..80], [2; 16]);
+    assert_eq!(body[80..96], [0; 16]);
+
+    let chunks = parse_mul(&body).unwrap();
+    assert_eq!(chunks.len(), 2);
+    assert_eq!(chunks[0].size, 64);
+    assert_eq!(chunks[1].contents.as_deref(), Some(&[9; 3][..]));
+}
+
+#[test]
+fn uneven_audio_chunks() {
+    let layout = [
+        Chunk {
+            variant: 0,
+            header: vec![0; 24],
+            size: 35,
+            contents: None,
+        },
+        Chunk {
+            variant: 1,
+            header: vec![0; 8],
+            size: 3,
+            contents: Some(vec![9; 3]),
+        },
+    ];
+    let channels = [(vec![1; 17], false), (vec![2; 17], false)];
+    let (body, channel_size) = multiplex(&layout, &channels);
+    assert_eq!(channel_size, 17);
+    assert_eq!(body[4..8], 51u32.to_le_bytes());
+    assert_eq!(body[66], 0);
+
+    let chunks = parse_mul(&body).unwrap();
+    assert_eq!(chunks.len(), 2);
+    assert_eq!(chunks[0].size, 35);
+    assert_eq!(chunks[1].contents.as_deref(), Some(&[9; 3][..]));
+}
+
+#[test]
+fn timeline_headers() {
+    let entries = parse_csv(
+        "kind,type,offset,size,header,file\r\n\
+         audio,0,0x800,32,0102030405060708200000000000000000000000ff000000,\r\n\
+         data,1,0x838,3,0909090909090909,data.bin\r\n",
+    )
+    .unwrap();
+    let header = parse_hex(entries[0].header.as_deref().unwrap()).unwrap();
+    assert_eq!(header.len(), 24);
+    assert_eq!(header[..8], [1, 2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(header[20], 0xFF);
+    assert_eq!(entries[1].file.as_deref(), Some("data.bin"));
+    assert_eq!(parse_hex("0g"), None);
+    assert_eq!(parse_hex("123"), None);
+
+    let entries = parse_csv("kind,type,size,file\r\naudio,0,32,\r\n").unwrap();
+    assert!(entries[0].header.is_none());
+}
@@ -25,10 +25,12 @@ fn main() {
     let file = std::fs::read(&args.input).expect("unable to load file");
     let mut bytes = file.iter().copied();
 
-    let (loop_start, loop_end) = args
-        .loop_marker
-        .then_some(find_loops(&file))
-        .unwrap_or_default();
+    let loops = find_loops(&file);
+    let (loop_start, loop_end) = if args.loop_marker {
+        loops
+    } else {
+        Default::default()
+    };
 
     // Check magic number
     let header = Header::load(&mut bytes);
@@ -0,0 +1,89 @@
+//! Decodes the SONY_4BIT_ADPCM of the SPU and interleaves its channels.
+
+/// The filter coefficients of the SPU, in 64ths.
+pub const FILTERS: [(i32, i32); 5] = [(0, 0), (60, 0), (115, -52), (98, -55), (122, -60)];
+
+/// The state of the SPU decoder between frames of a channel.
+#[derive(Default)]
+pub struct Decoder {
+    hist1: i32,
+    hist2: i32,
+}
+
+impl Decoder {
+    /// Decodes a frame the same way as the SPU, returning the samples before they get saturated.
+    pub fn decode(&mut self, frame: &[u8]) -> [i32; 28] {
+        // Shifts above 12 act like a shift of 9, and invalid filters like no filter
+        let shift = match frame[0] & 0xf {
+            shift @ 0..=12 => shift,
+            _ => 9,
+        };
+        let (k0, k1) = FILTERS
+            .get((frame[0] >> 4) as usize)
+            .copied()
+            .unwrap_or_default();
+
+        let mut samples = [0; 28];
+        for (i, sample) in samples.iter_mut().enumerate() {
+            let byte = frame[2 + i / 2];
+            let nibble = if i % 2 == 0 { byte << 4 } else { byte & 0xf0 };
+            let nibble = i32::from(nibble as i8 >> 4);
+
+            *sample = ((nibble << 12) >> shift) + ((self.hist1 * k0 + self.hist2 * k1 + 32) >> 6);
+            self.hist2 = self.hist1;
+            self.hist1 = (*sample).clamp(i16::MIN.into(), i16::MAX.into());
+        }
+        samples
+    }
+}
+
+/// Decodes a channel the same way as the SPU.
+pub fn decode(channel: &[u8]) -> Vec<i16> {
+    let mut decoder = Decoder::default();
+    channel
+        .chunks_exact(16)
+        .flat_map(|frame| decoder.decode(frame))
+        .map(|sample| sample.clamp(i16::MIN.into(), i16::MAX.into()) as i16)
+        .collect()
+}
+
+/// Interleaves the channels, padding them all to the same amount of blocks.
+///
+/// Zeros are silence in both PCM16 and ADPCM, where they make frames with no filter, shift or flag.
+pub fn interleaved<'a>(
+    channels: impl Iterator<Item = &'a [u8]> + Clone,
+    interleave: usize,
+) -> Vec<u8> {
+    let blocks = channels
+        .clone()
+        .map(|channel| channel.len().div_ceil(interleave))
+        .max()
+        .unwrap_or_default();
+    let mut body = vec![];
+    for i in 0..blocks {
+        for channel in channels.clone() {
+            let block = channel
+                .get(i * interleave..channel.len().min((i + 1) * interleave))
+                .unwrap_or_default();
+            body.extend_from_slice(block);
+            body.resize(body.len() + interleave - block.len(), 0);
+        }
+    }
+    body
+}
+
+#[test]
+fn decoding() {
+    let mut decoder = Decoder::default();
+    let mut frame = [0; 16];
+    frame[2] = 0x87;
+    let samples = decoder.decode(&frame);
+    assert_eq!(samples[0..3], [7 << 12, -8 << 12, 0]);
+
+    // Filter 1 with no shift overflows on the second sample
+    frame[0] = 0x10;
+    frame[2] = 0x77;
+    let samples = decoder.decode(&frame);
+    assert_eq!(samples[1], (7 << 12) + ((28672 * 60 + 32) >> 6));
+    assert_eq!(decode(&[frame, frame].concat())[1], i16::MAX);
+}
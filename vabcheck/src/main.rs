@@ -0,0 +1,622 @@
+#![allow(dead_code)]
+
+use std::{fmt::Display, fs::File, io::Write, ops::Range, path::PathBuf, slice::Iter};
+
+use clap::Parser;
+
+const PROGRAMS_OFFSET: usize = 0x20;
+const TONES_OFFSET: usize = PROGRAMS_OFFSET + 128 * 16;
+
+#[derive(Parser)]
+#[clap(version)]
+struct Args {
+    /// The `vab` file to check.
+    vab_path: PathBuf,
+    /// Rewrite the problems that can be repaired.
+    #[clap(long, short)]
+    fix: bool,
+    /// Output filename.
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let mut file = std::fs::read(&args.vab_path).unwrap();
+    let vab_file = VabFile::parse(&file).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(1);
+    });
+    vabheader::report_version(vab_file.header.version);
+
+    let problems = vab_file.check(&file);
+    for problem in &problems {
+        println!("{problem}");
+    }
+    println!("Problems found: {}", problems.len());
+
+    if !args.fix || problems.is_empty() {
+        return;
+    }
+
+    let fixed = problems
+        .iter()
+        .filter(|problem| problem.fix(&mut file))
+        .count();
+    println!("Problems fixed: {fixed}");
+
+    if fixed == 0 {
+        eprintln!("No problems could be fixed, aborting");
+        return;
+    }
+
+    let mut output = File::create(args.output.unwrap_or_else(|| {
+        format!(
+            "{}_fixed.{}",
+            args.vab_path.with_extension("").to_string_lossy(),
+            args.vab_path.extension().unwrap().to_string_lossy(),
+        )
+        .into()
+    }))
+    .unwrap();
+    output.write_all(&file).unwrap();
+}
+
+#[derive(Debug)]
+enum Problem {
+    /// The `total_size` field doesn't match the length of the file.
+    TotalSize { found: u32, expected: u32 },
+    /// The VAG sizes don't add up to the length of the body.
+    VagSizes {
+        found: usize,
+        expected: usize,
+        /// Where the size of the last VAG is stored and what it should be.
+        last_size: Option<(usize, usize)>,
+    },
+    /// A tone points to a VAG that doesn't exist.
+    SampleNumber {
+        program: usize,
+        tone: usize,
+        sample: u16,
+    },
+    /// A tone doesn't point back to the program it belongs to.
+    ParentProgram {
+        program: usize,
+        tone: usize,
+        found: u16,
+        offset: usize,
+    },
+    /// A tone has its lowest key above its highest key.
+    KeyRange {
+        program: usize,
+        tone: usize,
+        key_low: u8,
+        key_high: u8,
+        offset: usize,
+    },
+    /// A VAG has no frame with the end flag, `offset` is the flag of its last frame.
+    MissingEndFlag { vag: usize, offset: Option<usize> },
+}
+
+impl Problem {
+    /// Repairs the problem in `bytes`, returning whether it could be fixed.
+    fn fix(&self, bytes: &mut [u8]) -> bool {
+        match self {
+            Self::TotalSize { expected, .. } => {
+                bytes[12..16].copy_from_slice(&expected.to_le_bytes());
+                true
+            }
+            Self::VagSizes {
+                last_size: Some((offset, size)),
+                ..
+            } => {
+                bytes[*offset..*offset + 2].copy_from_slice(&((size / 8) as u16).to_le_bytes());
+                true
+            }
+            Self::ParentProgram {
+                program, offset, ..
+            } => {
+                bytes[offset + 20..offset + 22].copy_from_slice(&(*program as u16).to_le_bytes());
+                true
+            }
+            Self::KeyRange { offset, .. } => {
+                bytes.swap(offset + 6, offset + 7);
+                true
+            }
+            Self::MissingEndFlag {
+                offset: Some(offset),
+                ..
+            } => {
+                bytes[*offset] |= 1;
+                true
+            }
+            Self::VagSizes {
+                last_size: None, ..
+            }
+            | Self::SampleNumber { .. }
+            | Self::MissingEndFlag { offset: None, .. } => false,
+        }
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TotalSize { found, expected } => write!(
+                f,
+                "Total size is {found} bytes, but the file is {expected} bytes"
+            ),
+            Self::VagSizes {
+                found, expected, ..
+            } => write!(
+                f,
+                "VAG sizes add up to {found} bytes, but the body is {expected} bytes"
+            ),
+            Self::SampleNumber {
+                program,
+                tone,
+                sample,
+            } => write!(
+                f,
+                "Program {program} tone {tone} uses sample {sample}, which doesn't exist"
+            ),
+            Self::ParentProgram {
+                program,
+                tone,
+                found,
+                ..
+            } => write!(
+                f,
+                "Program {program} tone {tone} has parent program {found}"
+            ),
+            Self::KeyRange {
+                program,
+                tone,
+                key_low,
+                key_high,
+                ..
+            } => write!(
+                f,
+                "Program {program} tone {tone} has key range {key_low}-{key_high}"
+            ),
+            Self::MissingEndFlag { vag, .. } => write!(f, "VAG {vag} has no end flag"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct VabFile {
+    header: VabHeader,
+    /// The slot in the program table of every program in `programs`.
+    program_slots: Vec<usize>,
+    programs: Vec<Program>,
+    tones: Vec<Vec<Tone>>,
+    vag_sizes: Vec<usize>,
+    vag_ranges: Vec<Range<usize>>,
+}
+
+impl VabFile {
+    /// Parses the tables of the file, failing if they are cut short or don't fit their space,
+    /// since the rest of the file can't be found then.
+    fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let truncated = |table: &str| format!("The file is too small to hold its {table}");
+        let header = VabHeader::parse(
+            &mut bytes
+                .get(..PROGRAMS_OFFSET)
+                .ok_or_else(|| truncated("header"))?
+                .iter(),
+        );
+
+        let (program_slots, programs): (Vec<_>, Vec<_>) = bytes
+            .get(PROGRAMS_OFFSET..TONES_OFFSET)
+            .ok_or_else(|| truncated("program table"))?
+            .chunks_exact(16)
+            .enumerate()
+            .filter_map(|(slot, program)| Some((slot, Program::parse(&mut program.iter())?)))
+            .take(header.programs_number as usize)
+            .unzip();
+        if programs.len() < header.programs_number as usize {
+            return Err(format!(
+                "The header has {} programs, but only {} were found in the program table",
+                header.programs_number,
+                programs.len()
+            ));
+        }
+        for (slot, program) in program_slots.iter().zip(&programs) {
+            if program.tones_number > 16 {
+                return Err(format!(
+                    "Program {slot} has {} tones, but there is only room for 16",
+                    program.tones_number
+                ));
+            }
+        }
+
+        let vag_table_offset = TONES_OFFSET + programs.len() * 512;
+        let tones = bytes
+            .get(TONES_OFFSET..vag_table_offset)
+            .ok_or_else(|| truncated("tone tables"))?
+            .chunks_exact(512)
+            .zip(&programs)
+            .map(|(table, program)| {
+                table
+                    .chunks_exact(32)
+                    .take(program.tones_number as usize)
+                    .map(|tone| Tone::parse(&mut tone.iter()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        // The first entry of the VAG table is always 0
+        if header.vags_number > 255 {
+            return Err(format!(
+                "The header has {} VAGs, but there is only room for 255",
+                header.vags_number
+            ));
+        }
+        let start_of_samples = vag_table_offset + 512;
+        let vag_sizes: Vec<usize> = bytes
+            .get(vag_table_offset + 2..start_of_samples)
+            .ok_or_else(|| truncated("VAG table"))?
+            .chunks_exact(2)
+            .take(header.vags_number as usize)
+            .map(|size| u16::from_le_bytes([size[0], size[1]]) as usize * 8)
+            .collect();
+
+        let vag_ranges = vag_sizes
+            .iter()
+            .fold((vec![], start_of_samples), |(mut acc, cursor), size| {
+                acc.push(cursor..cursor + *size);
+                (acc, cursor + *size)
+            })
+            .0;
+
+        Ok(Self {
+            header,
+            program_slots,
+            programs,
+            tones,
+            vag_sizes,
+            vag_ranges,
+        })
+    }
+
+    fn check(&self, bytes: &[u8]) -> Vec<Problem> {
+        let mut problems = vec![];
+
+        if self.header.total_size as usize != bytes.len() {
+            problems.push(Problem::TotalSize {
+                found: self.header.total_size,
+                expected: bytes.len() as u32,
+            });
+        }
+
+        let vag_table_offset = TONES_OFFSET + self.programs.len() * 512 + 2;
+        let start_of_samples = vag_table_offset + 510;
+        let body_size = bytes.len() - start_of_samples;
+        let vag_sizes_sum = self.vag_sizes.iter().sum::<usize>();
+        if vag_sizes_sum != body_size {
+            let others = vag_sizes_sum - self.vag_sizes.last().copied().unwrap_or_default();
+            let last_size = (!self.vag_sizes.is_empty()
+                && body_size > others
+                && (body_size - others).is_multiple_of(8)
+                && (body_size - others) / 8 <= u16::MAX as usize)
+                .then(|| {
+                    (
+                        vag_table_offset + (self.vag_sizes.len() - 1) * 2,
+                        body_size - others,
+                    )
+                });
+            problems.push(Problem::VagSizes {
+                found: vag_sizes_sum,
+                expected: body_size,
+                last_size,
+            });
+        }
+
+        for (i, (slot, tones)) in self.program_slots.iter().zip(&self.tones).enumerate() {
+            for (j, tone) in tones.iter().enumerate() {
+                let offset = TONES_OFFSET + i * 512 + j * 32;
+                if tone.sample_number == 0 || tone.sample_number > self.header.vags_number {
+                    problems.push(Problem::SampleNumber {
+                        program: *slot,
+                        tone: j,
+                        sample: tone.sample_number,
+                    });
+                }
+                if tone.parent_program as usize != *slot {
+                    problems.push(Problem::ParentProgram {
+                        program: *slot,
+                        tone: j,
+                        found: tone.parent_program,
+                        offset,
+                    });
+                }
+                if tone.key_low > tone.key_high {
+                    problems.push(Problem::KeyRange {
+                        program: *slot,
+                        tone: j,
+                        key_low: tone.key_low,
+                        key_high: tone.key_high,
+                        offset,
+                    });
+                }
+            }
+        }
+
+        for (i, range) in self.vag_ranges.iter().enumerate() {
+            let range = range.start.min(bytes.len())..range.end.min(bytes.len());
+            let frames = bytes[range.clone()].chunks_exact(16);
+            if frames.len() == 0 {
+                problems.push(Problem::MissingEndFlag {
+                    vag: i + 1,
+                    offset: None,
+                });
+            } else if !frames.clone().any(|frame| frame[1] & 1 != 0) {
+                problems.push(Problem::MissingEndFlag {
+                    vag: i + 1,
+                    offset: Some(range.start + (frames.len() - 1) * 16 + 1),
+                });
+            }
+        }
+
+        problems
+    }
+}
+
+#[derive(Debug)]
+struct VabHeader {
+    magic_number: u32,
+    version: u32,
+    vab_id: u32,
+    total_size: u32,
+    _pad0: u16,
+    programs_number: u16,
+    tones_number: u16,
+    vags_number: u16,
+    master_volume: u8,
+    master_pan: u8,
+    bank_attributes_1: u8,
+    bank_attributes_2: u8,
+    _pad1: u32,
+}
+
+impl VabHeader {
+    fn parse(bytes: &mut Iter<u8>) -> Self {
+        Self {
+            magic_number: u32::from_le_bytes([
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+            ]),
+            version: u32::from_le_bytes([
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+            ]),
+            vab_id: u32::from_le_bytes([
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+            ]),
+            total_size: u32::from_le_bytes([
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+            ]),
+            _pad0: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            programs_number: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            tones_number: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            vags_number: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            master_volume: *bytes.next().unwrap(),
+            master_pan: *bytes.next().unwrap(),
+            bank_attributes_1: *bytes.next().unwrap(),
+            bank_attributes_2: *bytes.next().unwrap(),
+            _pad1: u32::from_le_bytes([
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+            ]),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Program {
+    tones_number: u8,
+    volume: u8,
+    priority: u8,
+    mode: u8,
+    pan: u8,
+    _pad0: u8,
+    attribute: u16,
+    _pad1: u32,
+    _pad2: u32,
+}
+
+impl Program {
+    fn parse(bytes: &mut Iter<u8>) -> Option<Self> {
+        let program = Self {
+            tones_number: *bytes.next().unwrap(),
+            volume: *bytes.next().unwrap(),
+            priority: *bytes.next().unwrap(),
+            mode: *bytes.next().unwrap(),
+            pan: *bytes.next().unwrap(),
+            _pad0: *bytes.next().unwrap(),
+            attribute: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            _pad1: u32::from_le_bytes([
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+            ]),
+            _pad2: u32::from_le_bytes([
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+            ]),
+        };
+
+        if program.tones_number == 0 {
+            None
+        } else {
+            Some(program)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Tone {
+    priority: u8,
+    reverb_mode: u8,
+    volume: u8,
+    pan: u8,
+    unity_key: u8,
+    pitch_tune: u8,
+    key_low: u8,
+    key_high: u8,
+    vibrato_width: u8,
+    vibrato_time: u8,
+    port_width: u8,
+    port_hold: u8,
+    pitch_bend_minimum: u8,
+    pitch_bend_maximum: u8,
+    _pad0: u8,
+    _pad1: u8,
+    adsr1: u16,
+    adsr2: u16,
+    parent_program: u16,
+    sample_number: u16,
+    _pad2: u16,
+    _pad3: u16,
+    _pad4: u16,
+    _pad5: u16,
+}
+
+impl Tone {
+    fn parse(bytes: &mut Iter<u8>) -> Self {
+        Self {
+            priority: *bytes.next().unwrap(),
+            reverb_mode: *bytes.next().unwrap(),
+            volume: *bytes.next().unwrap(),
+            pan: *bytes.next().unwrap(),
+            unity_key: *bytes.next().unwrap(),
+            pitch_tune: *bytes.next().unwrap(),
+            key_low: *bytes.next().unwrap(),
+            key_high: *bytes.next().unwrap(),
+            vibrato_width: *bytes.next().unwrap(),
+            vibrato_time: *bytes.next().unwrap(),
+            port_width: *bytes.next().unwrap(),
+            port_hold: *bytes.next().unwrap(),
+            pitch_bend_minimum: *bytes.next().unwrap(),
+            pitch_bend_maximum: *bytes.next().unwrap(),
+            _pad0: *bytes.next().unwrap(),
+            _pad1: *bytes.next().unwrap(),
+            adsr1: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            adsr2: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            parent_program: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            sample_number: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            _pad2: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            _pad3: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            _pad4: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            _pad5: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+        }
+    }
+}
+
+#[test]
+fn check_and_fix() {
+    let vag_table_offset = TONES_OFFSET + 512;
+    let body_offset = vag_table_offset + 512;
+    let mut bytes = vec![0; body_offset + 64];
+    bytes[..4].copy_from_slice(b"pBAV");
+    bytes[4] = 7;
+    bytes[0x12] = 1;
+    bytes[0x14] = 2;
+    bytes[0x16] = 2;
+    // The only program is in slot 3
+    bytes[PROGRAMS_OFFSET + 3 * 16] = 2;
+    let tone = TONES_OFFSET;
+    bytes[tone + 6] = 60;
+    bytes[tone + 7] = 40;
+    bytes[tone + 22] = 1;
+    let tone = TONES_OFFSET + 32;
+    bytes[tone + 7] = 127;
+    bytes[tone + 20] = 3;
+    bytes[tone + 22] = 5;
+    // The second VAG is 32 bytes long, but the table says 16, and only the first one ends
+    bytes[vag_table_offset + 2] = 4;
+    bytes[vag_table_offset + 4] = 2;
+    bytes[body_offset + 16 + 1] = 1;
+
+    let vab_file = VabFile::parse(&bytes).unwrap();
+    let problems = vab_file.check(&bytes);
+    assert_eq!(
+        problems.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        [
+            format!(
+                "Total size is 0 bytes, but the file is {} bytes",
+                bytes.len()
+            ),
+            "VAG sizes add up to 48 bytes, but the body is 64 bytes".to_owned(),
+            "Program 3 tone 0 has parent program 0".to_owned(),
+            "Program 3 tone 0 has key range 60-40".to_owned(),
+            "Program 3 tone 1 uses sample 5, which doesn't exist".to_owned(),
+            "VAG 2 has no end flag".to_owned(),
+        ]
+    );
+
+    let mut fixed = bytes.clone();
+    assert_eq!(
+        problems
+            .iter()
+            .filter(|problem| problem.fix(&mut fixed))
+            .count(),
+        5
+    );
+    assert_eq!(fixed[12..16], (bytes.len() as u32).to_le_bytes());
+    assert_eq!(fixed[vag_table_offset + 4..vag_table_offset + 6], [4, 0]);
+    assert_eq!(fixed[TONES_OFFSET + 20..TONES_OFFSET + 22], [3, 0]);
+    assert_eq!(fixed[TONES_OFFSET + 6..TONES_OFFSET + 8], [40, 60]);
+    assert_eq!(fixed[body_offset + 32 + 1], 1);
+
+    let vab_file = VabFile::parse(&fixed).unwrap();
+    assert_eq!(vab_file.check(&fixed).len(), 1);
+}
+
+#[test]
+fn broken_tables() {
+    let mut bytes = vec![0; TONES_OFFSET + 512 * 2];
+    bytes[4] = 7;
+    bytes[0x12] = 1;
+    bytes[PROGRAMS_OFFSET] = 1;
+    assert_eq!(
+        VabFile::parse(&bytes[..0x10]).unwrap_err(),
+        "The file is too small to hold its header"
+    );
+    assert_eq!(
+        VabFile::parse(&bytes[..TONES_OFFSET + 0x100]).unwrap_err(),
+        "The file is too small to hold its tone tables"
+    );
+    assert_eq!(
+        VabFile::parse(&bytes[..TONES_OFFSET + 512 + 0x100]).unwrap_err(),
+        "The file is too small to hold its VAG table"
+    );
+
+    bytes[PROGRAMS_OFFSET] = 17;
+    assert_eq!(
+        VabFile::parse(&bytes).unwrap_err(),
+        "Program 0 has 17 tones, but there is only room for 16"
+    );
+    bytes[PROGRAMS_OFFSET] = 0;
+    assert_eq!(
+        VabFile::parse(&bytes).unwrap_err(),
+        "The header has 1 programs, but only 0 were found in the program table"
+    );
+}
@@ -21,7 +21,11 @@ fn main() {
     let file_len = file.len();
     let mut file_iter = file.iter_mut();
 
-    VabFile::parse(&mut file_iter, file_len, args.psx);
+    let vab_file = VabFile::parse(&mut file_iter, file_len, args.psx).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(1);
+    });
+    vabheader::report_version(vab_file.header.version);
 
     let out_path = if args.psx {
         format!(
@@ -48,12 +52,9 @@ struct VabFile {
 }
 
 impl VabFile {
-    fn parse(bytes: &mut IterMut<u8>, file_len: usize, psx: bool) -> Self {
+    fn parse(bytes: &mut IterMut<u8>, file_len: usize, psx: bool) -> Result<Self, String> {
         let header = VabHeader::parse(bytes);
-        assert!(
-            (file_len as u32) >= header.total_size,
-            "File size mismatch!"
-        );
+        vabheader::check_total_size(header.total_size, file_len)?;
 
         let mut programs = Vec::with_capacity(header.programs_number as usize);
         let mut program_space = 0;
@@ -112,13 +113,13 @@ impl VabFile {
             })
             .0;
 
-        Self {
+        Ok(Self {
             header,
             programs,
             tones,
             vag_sizes,
             vag_ranges,
-        }
+        })
     }
 }
 
@@ -0,0 +1,33 @@
+//! Checks on the VAB header shared by the tools that read VAB files.
+
+use std::ops::RangeInclusive;
+
+/// The versions of the VAB format known to share the same layout.
+pub const KNOWN_VERSIONS: RangeInclusive<u32> = 5..=7;
+
+/// Prints the version of a VAB file, warning about the ones that aren't known.
+///
+/// Files of other versions are still read with the layout of the known ones.
+pub fn report_version(version: u32) {
+    println!("VAB version: {version}");
+    if !KNOWN_VERSIONS.contains(&version) {
+        eprintln!("Unknown VAB version {version}, reading it like versions 5 to 7");
+    }
+}
+
+/// Checks that the file is at least as long as the total size written in its header.
+pub fn check_total_size(total_size: u32, file_len: usize) -> Result<(), String> {
+    if total_size as usize > file_len {
+        return Err(format!(
+            "The header gives a total size of {total_size} bytes, but the file is only {file_len} bytes long"
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn total_size() {
+    assert!(check_total_size(0x1000, 0x1000).is_ok());
+    assert!(check_total_size(0x1000, 0x2000).is_ok());
+    assert!(check_total_size(0x1000, 0x800).is_err());
+}
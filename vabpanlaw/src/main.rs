@@ -0,0 +1,346 @@
+#![allow(dead_code)]
+
+use std::{fs::File, io::Write, path::PathBuf, slice::IterMut};
+
+use clap::Parser;
+
+enum Function {
+    Attenuate,
+    Amplify,
+}
+
+impl Function {
+    /// The volume multiplier for a tone, with `pan` going from -1.0 (left) to 1.0 (right).
+    fn volume_scale(&self, pan: f32, law: f32) -> f32 {
+        let shift = pan.abs() * law;
+        match self {
+            Self::Attenuate => 10.0_f32.powf(-shift / 20.0),
+            Self::Amplify => 10.0_f32.powf(shift / 20.0),
+        }
+    }
+}
+
+/// The position of a VAB pan, from -1.0 (left) to 1.0 (right).
+///
+/// The centre is at 64, so the right side is one step shorter than the left one.
+fn pan_position(pan: u8) -> f32 {
+    if pan < 64 {
+        (f32::from(pan) - 64.0) / 64.0
+    } else {
+        ((f32::from(pan) - 64.0) / 63.0).min(1.0)
+    }
+}
+
+#[derive(Parser)]
+#[clap(version)]
+struct Args {
+    vab_path: PathBuf,
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+    /// The pan law in dB, usually 0, 3.01 or 6.02.
+    #[clap(short, long, default_value_t = 3.01, allow_negative_numbers = true)]
+    law: f32,
+    /// DEFAULT
+    #[clap(long)]
+    attenuate: bool,
+    #[clap(long)]
+    amplify: bool,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let function = if args.amplify {
+        Function::Amplify
+    } else {
+        Function::Attenuate
+    };
+
+    let mut file = std::fs::read(&args.vab_path).unwrap();
+    let file_len = file.len();
+    let mut file_iter = file.iter_mut();
+
+    let vab_file =
+        VabFile::parse(&mut file_iter, file_len, &function, args.law).unwrap_or_else(|err| {
+            eprintln!("{err}");
+            std::process::exit(1);
+        });
+    vabheader::report_version(vab_file.header.version);
+
+    let tone_count = vab_file.tones.iter().map(Vec::len).sum::<usize>();
+    let changed_volumes = vab_file
+        .tones
+        .iter()
+        .flatten()
+        .filter(|tone| tone.volume != tone.original_volume)
+        .count();
+    println!("Tones found: {tone_count}");
+    println!("Changed Volumes: {changed_volumes}");
+
+    // A 0dB law changes nothing, but still gets written so it can be used like the others
+    if changed_volumes == 0 {
+        eprintln!("No volumes changed");
+    }
+
+    let mut output = File::create(args.output.unwrap_or_else(|| {
+        format!(
+            "{}_{}.{}",
+            args.vab_path.with_extension("").to_string_lossy(),
+            if args.amplify {
+                "amplified"
+            } else {
+                "attenuated"
+            },
+            args.vab_path.extension().unwrap().to_string_lossy(),
+        )
+        .into()
+    }))
+    .unwrap();
+    output.write_all(&file).unwrap();
+}
+
+#[derive(Debug)]
+struct VabFile {
+    header: VabHeader,
+    programs: Vec<Program>,
+    tones: Vec<Vec<Tone>>,
+}
+
+impl VabFile {
+    fn parse(
+        bytes: &mut IterMut<u8>,
+        file_len: usize,
+        function: &Function,
+        law: f32,
+    ) -> Result<Self, String> {
+        let header = VabHeader::parse(bytes);
+        vabheader::check_total_size(header.total_size, file_len)?;
+
+        let mut programs = Vec::with_capacity(header.programs_number as usize);
+        let mut program_space = 0;
+        while programs.len() < header.programs_number as usize {
+            if let Some(program) = Program::parse(bytes) {
+                programs.push(program);
+            }
+            program_space += 1;
+        }
+        for _ in 0..16 * (128 - program_space) {
+            bytes.next().unwrap();
+        }
+
+        let tones = programs
+            .iter()
+            .map(|program| {
+                let tones = (0..program.tones_number)
+                    .map(|_| Tone::parse(bytes, function, law))
+                    .collect::<Vec<_>>();
+
+                for _ in 0..32 * (16 - program.tones_number as usize) {
+                    bytes.next().unwrap();
+                }
+
+                tones
+            })
+            .collect::<Vec<_>>();
+
+        Ok(Self {
+            header,
+            programs,
+            tones,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct VabHeader {
+    magic_number: u32,
+    version: u32,
+    vab_id: u32,
+    total_size: u32,
+    _pad0: u16,
+    programs_number: u16,
+    tones_number: u16,
+    vags_number: u16,
+    master_volume: u8,
+    master_pan: u8,
+    bank_attributes_1: u8,
+    bank_attributes_2: u8,
+    _pad1: u32,
+}
+
+impl VabHeader {
+    fn parse(bytes: &mut IterMut<u8>) -> Self {
+        Self {
+            magic_number: u32::from_le_bytes([
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+            ]),
+            version: u32::from_le_bytes([
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+            ]),
+            vab_id: u32::from_le_bytes([
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+            ]),
+            total_size: u32::from_le_bytes([
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+            ]),
+            _pad0: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            programs_number: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            tones_number: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            vags_number: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            master_volume: *bytes.next().unwrap(),
+            master_pan: *bytes.next().unwrap(),
+            bank_attributes_1: *bytes.next().unwrap(),
+            bank_attributes_2: *bytes.next().unwrap(),
+            _pad1: u32::from_le_bytes([
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+            ]),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Program {
+    tones_number: u8,
+    volume: u8,
+    priority: u8,
+    mode: u8,
+    pan: u8,
+    _pad0: u8,
+    attribute: u16,
+    _pad1: u32,
+    _pad2: u32,
+}
+
+impl Program {
+    fn parse(bytes: &mut IterMut<u8>) -> Option<Self> {
+        let program = Self {
+            tones_number: *bytes.next().unwrap(),
+            volume: *bytes.next().unwrap(),
+            priority: *bytes.next().unwrap(),
+            mode: *bytes.next().unwrap(),
+            pan: *bytes.next().unwrap(),
+            _pad0: *bytes.next().unwrap(),
+            attribute: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            _pad1: u32::from_le_bytes([
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+            ]),
+            _pad2: u32::from_le_bytes([
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+            ]),
+        };
+
+        if program.tones_number == 0 {
+            None
+        } else {
+            Some(program)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Tone {
+    priority: u8,
+    reverb_mode: u8,
+    original_volume: u8,
+    volume: u8,
+    pan: u8,
+    unity_key: u8,
+    pitch_tune: u8,
+    key_low: u8,
+    key_high: u8,
+    vibrato_width: u8,
+    vibrato_time: u8,
+    port_width: u8,
+    port_hold: u8,
+    pitch_bend_minimum: u8,
+    pitch_bend_maximum: u8,
+    _pad0: u8,
+    _pad1: u8,
+    adsr1: u16,
+    adsr2: u16,
+    parent_program: u16,
+    sample_number: u16,
+    _pad2: u16,
+    _pad3: u16,
+    _pad4: u16,
+    _pad5: u16,
+}
+
+impl Tone {
+    fn parse(bytes: &mut IterMut<u8>, function: &Function, law: f32) -> Self {
+        let priority = *bytes.next().unwrap();
+        let reverb_mode = *bytes.next().unwrap();
+        let volume = bytes.next().unwrap();
+        let pan = *bytes.next().unwrap();
+
+        let original_volume = *volume;
+        *volume = (f32::from(*volume) * function.volume_scale(pan_position(pan), law))
+            .round()
+            .clamp(0.0, 127.0) as u8;
+
+        Self {
+            priority,
+            reverb_mode,
+            original_volume,
+            volume: *volume,
+            pan,
+            unity_key: *bytes.next().unwrap(),
+            pitch_tune: *bytes.next().unwrap(),
+            key_low: *bytes.next().unwrap(),
+            key_high: *bytes.next().unwrap(),
+            vibrato_width: *bytes.next().unwrap(),
+            vibrato_time: *bytes.next().unwrap(),
+            port_width: *bytes.next().unwrap(),
+            port_hold: *bytes.next().unwrap(),
+            pitch_bend_minimum: *bytes.next().unwrap(),
+            pitch_bend_maximum: *bytes.next().unwrap(),
+            _pad0: *bytes.next().unwrap(),
+            _pad1: *bytes.next().unwrap(),
+            adsr1: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            adsr2: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            parent_program: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            sample_number: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            _pad2: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            _pad3: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            _pad4: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            _pad5: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+        }
+    }
+}
+
+#[test]
+fn pan_laws() {
+    assert_eq!(pan_position(0), -1.0);
+    assert_eq!(pan_position(64), 0.0);
+    assert_eq!(pan_position(127), 1.0);
+    assert_eq!(pan_position(255), 1.0);
+
+    let attenuate = |pan, law| Function::Attenuate.volume_scale(pan_position(pan), law);
+    assert_eq!(attenuate(64, 6.02), 1.0);
+    assert_eq!(attenuate(0, 0.0), 1.0);
+    assert_eq!(attenuate(127, 0.0), 1.0);
+    assert!((attenuate(0, 3.01) - std::f32::consts::FRAC_1_SQRT_2).abs() < 0.001);
+    assert!((attenuate(127, 6.02) - 0.5).abs() < 0.001);
+    assert!((Function::Amplify.volume_scale(-1.0, 6.02) - 2.0).abs() < 0.001);
+}
@@ -0,0 +1,749 @@
+#![allow(dead_code)]
+
+use std::{fs::File, io::Write, path::PathBuf, slice::Iter};
+
+use clap::Parser;
+use serde::Deserialize;
+
+const PROGRAMS_OFFSET: usize = 0x20;
+const TONES_OFFSET: usize = PROGRAMS_OFFSET + 128 * 16;
+
+#[derive(Parser)]
+#[clap(version)]
+struct Args {
+    /// The `vab` file to patch.
+    vab_path: PathBuf,
+    /// The edit file to apply, either `toml` or `json`.
+    patch_path: PathBuf,
+    /// Only print the changes without writing the output file.
+    #[clap(long, short)]
+    dry_run: bool,
+    /// Output filename.
+    #[clap(long, short)]
+    output: Option<PathBuf>,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let patch_text = std::fs::read_to_string(&args.patch_path).unwrap();
+    let patch = Patch::parse(&patch_text, &args.patch_path).unwrap_or_else(|err| {
+        eprintln!("Invalid edit file: {err}");
+        std::process::exit(1);
+    });
+
+    let mut file = std::fs::read(&args.vab_path).unwrap();
+    let mut vab_file = VabFile::parse(&mut file.iter(), file.len()).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(1);
+    });
+    vabheader::report_version(vab_file.header.version);
+
+    let changes = vab_file.apply(&patch).unwrap_or_else(|err| {
+        eprintln!("Invalid edit: {err}");
+        std::process::exit(1);
+    });
+
+    println!(
+        "{:<8} {:<5} {:<7} {:<20} {:>6} {:>6}",
+        "Program", "Tone", "Sample", "Field", "Before", "After"
+    );
+    for change in &changes {
+        println!("{change}");
+    }
+    println!("Fields changed: {}", changes.len());
+
+    if args.dry_run {
+        return;
+    }
+    if changes.is_empty() {
+        eprintln!("No fields changed, aborting");
+        return;
+    }
+
+    vab_file.write(&mut file);
+    let mut output = File::create(args.output.unwrap_or_else(|| {
+        format!(
+            "{}_patched.{}",
+            args.vab_path.with_extension("").to_string_lossy(),
+            args.vab_path.extension().unwrap().to_string_lossy(),
+        )
+        .into()
+    }))
+    .unwrap();
+    output.write_all(&file).unwrap();
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Patch {
+    #[serde(default)]
+    program: Vec<ProgramEdit>,
+    #[serde(default)]
+    tone: Vec<ToneEdit>,
+}
+
+impl Patch {
+    fn parse(text: &str, path: &std::path::Path) -> Result<Self, String> {
+        let patch: Self = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(text).map_err(|err| err.to_string())?
+        } else {
+            toml::from_str(text).map_err(|err| err.to_string())?
+        };
+
+        let operations = patch.program.iter().map(|edit| edit.operation());
+        for operation in operations.chain(patch.tone.iter().map(|edit| edit.operation())) {
+            operation.check_finite()?;
+        }
+        Ok(patch)
+    }
+}
+
+/// Either a single index or a list of indices to match against.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Selector {
+    One(u16),
+    Many(Vec<u16>),
+}
+
+impl Selector {
+    /// Whether `value` is selected, a missing selector matches everything.
+    fn matches(selector: &Option<Self>, value: u16) -> bool {
+        match selector {
+            None => true,
+            Some(Self::One(x)) => *x == value,
+            Some(Self::Many(xs)) => xs.contains(&value),
+        }
+    }
+}
+
+/// How a field gets changed, `set` can't be combined with the others while
+/// `scale` is applied before `offset`.
+///
+/// A `set` outside the range of the field is an error, while `scale` and `offset` results get
+/// clamped to it.
+#[derive(Debug, Default)]
+struct Operation {
+    set: Option<f64>,
+    scale: Option<f64>,
+    offset: Option<f64>,
+}
+
+impl Operation {
+    /// Fails on NaN and infinite values, which can't be clamped to a field.
+    fn check_finite(&self) -> Result<(), String> {
+        for (name, value) in [
+            ("set", self.set),
+            ("scale", self.scale),
+            ("offset", self.offset),
+        ] {
+            if let Some(value) = value.filter(|value| !value.is_finite()) {
+                return Err(format!("`{name} = {value}` isn't a finite number"));
+            }
+        }
+        Ok(())
+    }
+
+    /// The new value, and whether it had to be clamped.
+    fn apply(&self, value: u16, max: u16) -> Result<(u16, bool), String> {
+        let result = match (self.set, self.scale, self.offset) {
+            (Some(set), None, None) => {
+                if !(0.0..=f64::from(max)).contains(&set.round()) {
+                    return Err(format!("`set = {set}` is out of the range 0 to {max}"));
+                }
+                set
+            }
+            (None, None, None) => return Err("missing `set`, `scale` or `offset`".to_owned()),
+            (Some(_), _, _) => {
+                return Err("`set` can't be combined with `scale` or `offset`".to_owned())
+            }
+            (None, scale, offset) => {
+                f64::from(value) * scale.unwrap_or(1.0) + offset.unwrap_or(0.0)
+            }
+        };
+        let clamped = result.round().clamp(0.0, f64::from(max));
+        Ok((clamped as u16, clamped != result.round()))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProgramEdit {
+    program: Option<Selector>,
+    field: ProgramField,
+    set: Option<f64>,
+    scale: Option<f64>,
+    offset: Option<f64>,
+}
+
+impl ProgramEdit {
+    fn operation(&self) -> Operation {
+        Operation {
+            set: self.set,
+            scale: self.scale,
+            offset: self.offset,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ToneEdit {
+    program: Option<Selector>,
+    tone: Option<Selector>,
+    sample: Option<Selector>,
+    field: ToneField,
+    set: Option<f64>,
+    scale: Option<f64>,
+    offset: Option<f64>,
+}
+
+impl ToneEdit {
+    fn operation(&self) -> Operation {
+        Operation {
+            set: self.set,
+            scale: self.scale,
+            offset: self.offset,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ProgramField {
+    Volume,
+    Priority,
+    Mode,
+    Pan,
+    Attribute,
+}
+
+impl ProgramField {
+    fn max(self) -> u16 {
+        match self {
+            Self::Volume | Self::Priority | Self::Pan => 127,
+            Self::Mode => 255,
+            Self::Attribute => u16::MAX,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ToneField {
+    Priority,
+    #[serde(alias = "mode")]
+    ReverbMode,
+    Volume,
+    Pan,
+    #[serde(alias = "root_key")]
+    UnityKey,
+    PitchTune,
+    KeyLow,
+    KeyHigh,
+    VibratoWidth,
+    VibratoTime,
+    PortWidth,
+    PortHold,
+    #[serde(alias = "bend_min")]
+    PitchBendMinimum,
+    #[serde(alias = "bend_max")]
+    PitchBendMaximum,
+    Adsr1,
+    Adsr2,
+    AttackRate,
+    DecayRate,
+    SustainLevel,
+    SustainRate,
+    ReleaseRate,
+}
+
+impl ToneField {
+    fn max(self) -> u16 {
+        match self {
+            Self::ReverbMode
+            | Self::VibratoWidth
+            | Self::VibratoTime
+            | Self::PortWidth
+            | Self::PortHold => 255,
+            Self::Adsr1 | Self::Adsr2 => u16::MAX,
+            Self::AttackRate | Self::SustainRate => 0x7f,
+            Self::DecayRate | Self::SustainLevel => 0xf,
+            Self::ReleaseRate => 0x1f,
+            _ => 127,
+        }
+    }
+}
+
+struct Change {
+    program: usize,
+    tone: Option<(usize, u16)>,
+    field: String,
+    before: u16,
+    after: u16,
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (tone, sample) = match self.tone {
+            Some((tone, sample)) => (tone.to_string(), sample.to_string()),
+            None => ("-".to_owned(), "-".to_owned()),
+        };
+        write!(
+            f,
+            "{:<8} {tone:<5} {sample:<7} {:<20} {:>6} {:>6}",
+            self.program, self.field, self.before, self.after
+        )
+    }
+}
+
+#[derive(Debug)]
+struct VabFile {
+    header: VabHeader,
+    /// The slot in the program table of every program in `programs`.
+    program_slots: Vec<usize>,
+    programs: Vec<Program>,
+    tones: Vec<Vec<Tone>>,
+}
+
+impl VabFile {
+    fn parse(bytes: &mut Iter<u8>, file_len: usize) -> Result<Self, String> {
+        let header = VabHeader::parse(bytes);
+        vabheader::check_total_size(header.total_size, file_len)?;
+
+        let mut programs = Vec::with_capacity(header.programs_number as usize);
+        let mut program_slots = Vec::with_capacity(header.programs_number as usize);
+        let mut program_space = 0;
+        while programs.len() < header.programs_number as usize {
+            if let Some(program) = Program::parse(bytes) {
+                programs.push(program);
+                program_slots.push(program_space);
+            }
+            program_space += 1;
+        }
+        for _ in 0..16 * (128 - program_space) {
+            bytes.next().unwrap();
+        }
+
+        let tones = programs
+            .iter()
+            .map(|program| {
+                let tones = (0..program.tones_number)
+                    .map(|_| Tone::parse(bytes))
+                    .collect::<Vec<_>>();
+
+                for _ in 0..32 * (16 - program.tones_number as usize) {
+                    bytes.next().unwrap();
+                }
+
+                tones
+            })
+            .collect::<Vec<_>>();
+
+        Ok(Self {
+            header,
+            program_slots,
+            programs,
+            tones,
+        })
+    }
+
+    fn apply(&mut self, patch: &Patch) -> Result<Vec<Change>, String> {
+        let mut changes = vec![];
+
+        for edit in &patch.program {
+            let operation = edit.operation();
+            for (slot, program) in self.program_slots.iter().zip(&mut self.programs) {
+                if !Selector::matches(&edit.program, *slot as u16) {
+                    continue;
+                }
+                let before = program.get(edit.field);
+                let (after, clamped) = operation.apply(before, edit.field.max())?;
+                if clamped {
+                    eprintln!("Program {slot}: {:?} clamped to {after}", edit.field);
+                }
+                program.set(edit.field, after);
+                if before != after {
+                    changes.push(Change {
+                        program: *slot,
+                        tone: None,
+                        field: format!("{:?}", edit.field),
+                        before,
+                        after,
+                    });
+                }
+            }
+        }
+
+        for edit in &patch.tone {
+            let operation = edit.operation();
+            for (slot, tones) in self.program_slots.iter().zip(&mut self.tones) {
+                if !Selector::matches(&edit.program, *slot as u16) {
+                    continue;
+                }
+                for (i, tone) in tones.iter_mut().enumerate() {
+                    if !Selector::matches(&edit.tone, i as u16)
+                        || !Selector::matches(&edit.sample, tone.sample_number)
+                    {
+                        continue;
+                    }
+                    let before = tone.get(edit.field);
+                    let (after, clamped) = operation.apply(before, edit.field.max())?;
+                    if clamped {
+                        eprintln!(
+                            "Program {slot} tone {i}: {:?} clamped to {after}",
+                            edit.field
+                        );
+                    }
+                    tone.set(edit.field, after);
+                    if before != after {
+                        changes.push(Change {
+                            program: *slot,
+                            tone: Some((i, tone.sample_number)),
+                            field: format!("{:?}", edit.field),
+                            before,
+                            after,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Writes the programs and tones back over their original positions in `bytes`.
+    fn write(&self, bytes: &mut [u8]) {
+        for (slot, program) in self.program_slots.iter().zip(&self.programs) {
+            let offset = PROGRAMS_OFFSET + slot * 16;
+            bytes[offset..offset + 16].copy_from_slice(&program.to_bytes());
+        }
+        for (i, tones) in self.tones.iter().enumerate() {
+            for (j, tone) in tones.iter().enumerate() {
+                let offset = TONES_OFFSET + i * 512 + j * 32;
+                bytes[offset..offset + 32].copy_from_slice(&tone.to_bytes());
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct VabHeader {
+    magic_number: u32,
+    version: u32,
+    vab_id: u32,
+    total_size: u32,
+    _pad0: u16,
+    programs_number: u16,
+    tones_number: u16,
+    vags_number: u16,
+    master_volume: u8,
+    master_pan: u8,
+    bank_attributes_1: u8,
+    bank_attributes_2: u8,
+    _pad1: u32,
+}
+
+impl VabHeader {
+    fn parse(bytes: &mut Iter<u8>) -> Self {
+        Self {
+            magic_number: u32::from_le_bytes([
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+            ]),
+            version: u32::from_le_bytes([
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+            ]),
+            vab_id: u32::from_le_bytes([
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+            ]),
+            total_size: u32::from_le_bytes([
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+            ]),
+            _pad0: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            programs_number: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            tones_number: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            vags_number: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            master_volume: *bytes.next().unwrap(),
+            master_pan: *bytes.next().unwrap(),
+            bank_attributes_1: *bytes.next().unwrap(),
+            bank_attributes_2: *bytes.next().unwrap(),
+            _pad1: u32::from_le_bytes([
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+            ]),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Program {
+    tones_number: u8,
+    volume: u8,
+    priority: u8,
+    mode: u8,
+    pan: u8,
+    _pad0: u8,
+    attribute: u16,
+    _pad1: u32,
+    _pad2: u32,
+}
+
+impl Program {
+    fn parse(bytes: &mut Iter<u8>) -> Option<Self> {
+        let program = Self {
+            tones_number: *bytes.next().unwrap(),
+            volume: *bytes.next().unwrap(),
+            priority: *bytes.next().unwrap(),
+            mode: *bytes.next().unwrap(),
+            pan: *bytes.next().unwrap(),
+            _pad0: *bytes.next().unwrap(),
+            attribute: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            _pad1: u32::from_le_bytes([
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+            ]),
+            _pad2: u32::from_le_bytes([
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+                *bytes.next().unwrap(),
+            ]),
+        };
+
+        if program.tones_number == 0 {
+            None
+        } else {
+            Some(program)
+        }
+    }
+
+    fn to_bytes(&self) -> [u8; 16] {
+        let attribute = self.attribute.to_le_bytes();
+        let pad1 = self._pad1.to_le_bytes();
+        let pad2 = self._pad2.to_le_bytes();
+        [
+            self.tones_number,
+            self.volume,
+            self.priority,
+            self.mode,
+            self.pan,
+            self._pad0,
+            attribute[0],
+            attribute[1],
+            pad1[0],
+            pad1[1],
+            pad1[2],
+            pad1[3],
+            pad2[0],
+            pad2[1],
+            pad2[2],
+            pad2[3],
+        ]
+    }
+
+    fn get(&self, field: ProgramField) -> u16 {
+        match field {
+            ProgramField::Volume => self.volume.into(),
+            ProgramField::Priority => self.priority.into(),
+            ProgramField::Mode => self.mode.into(),
+            ProgramField::Pan => self.pan.into(),
+            ProgramField::Attribute => self.attribute,
+        }
+    }
+
+    fn set(&mut self, field: ProgramField, value: u16) {
+        match field {
+            ProgramField::Volume => self.volume = value as u8,
+            ProgramField::Priority => self.priority = value as u8,
+            ProgramField::Mode => self.mode = value as u8,
+            ProgramField::Pan => self.pan = value as u8,
+            ProgramField::Attribute => self.attribute = value,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Tone {
+    priority: u8,
+    reverb_mode: u8,
+    volume: u8,
+    pan: u8,
+    unity_key: u8,
+    pitch_tune: u8,
+    key_low: u8,
+    key_high: u8,
+    vibrato_width: u8,
+    vibrato_time: u8,
+    port_width: u8,
+    port_hold: u8,
+    pitch_bend_minimum: u8,
+    pitch_bend_maximum: u8,
+    _pad0: u8,
+    _pad1: u8,
+    adsr1: u16,
+    adsr2: u16,
+    parent_program: u16,
+    sample_number: u16,
+    _pad2: u16,
+    _pad3: u16,
+    _pad4: u16,
+    _pad5: u16,
+}
+
+impl Tone {
+    fn parse(bytes: &mut Iter<u8>) -> Self {
+        Self {
+            priority: *bytes.next().unwrap(),
+            reverb_mode: *bytes.next().unwrap(),
+            volume: *bytes.next().unwrap(),
+            pan: *bytes.next().unwrap(),
+            unity_key: *bytes.next().unwrap(),
+            pitch_tune: *bytes.next().unwrap(),
+            key_low: *bytes.next().unwrap(),
+            key_high: *bytes.next().unwrap(),
+            vibrato_width: *bytes.next().unwrap(),
+            vibrato_time: *bytes.next().unwrap(),
+            port_width: *bytes.next().unwrap(),
+            port_hold: *bytes.next().unwrap(),
+            pitch_bend_minimum: *bytes.next().unwrap(),
+            pitch_bend_maximum: *bytes.next().unwrap(),
+            _pad0: *bytes.next().unwrap(),
+            _pad1: *bytes.next().unwrap(),
+            adsr1: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            adsr2: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            parent_program: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            sample_number: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            _pad2: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            _pad3: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            _pad4: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+            _pad5: u16::from_le_bytes([*bytes.next().unwrap(), *bytes.next().unwrap()]),
+        }
+    }
+
+    fn to_bytes(&self) -> [u8; 32] {
+        [
+            [self.priority, self.reverb_mode],
+            [self.volume, self.pan],
+            [self.unity_key, self.pitch_tune],
+            [self.key_low, self.key_high],
+            [self.vibrato_width, self.vibrato_time],
+            [self.port_width, self.port_hold],
+            [self.pitch_bend_minimum, self.pitch_bend_maximum],
+            [self._pad0, self._pad1],
+            self.adsr1.to_le_bytes(),
+            self.adsr2.to_le_bytes(),
+            self.parent_program.to_le_bytes(),
+            self.sample_number.to_le_bytes(),
+            self._pad2.to_le_bytes(),
+            self._pad3.to_le_bytes(),
+            self._pad4.to_le_bytes(),
+            self._pad5.to_le_bytes(),
+        ]
+        .concat()
+        .try_into()
+        .unwrap()
+    }
+
+    fn get(&self, field: ToneField) -> u16 {
+        match field {
+            ToneField::Priority => self.priority.into(),
+            ToneField::ReverbMode => self.reverb_mode.into(),
+            ToneField::Volume => self.volume.into(),
+            ToneField::Pan => self.pan.into(),
+            ToneField::UnityKey => self.unity_key.into(),
+            ToneField::PitchTune => self.pitch_tune.into(),
+            ToneField::KeyLow => self.key_low.into(),
+            ToneField::KeyHigh => self.key_high.into(),
+            ToneField::VibratoWidth => self.vibrato_width.into(),
+            ToneField::VibratoTime => self.vibrato_time.into(),
+            ToneField::PortWidth => self.port_width.into(),
+            ToneField::PortHold => self.port_hold.into(),
+            ToneField::PitchBendMinimum => self.pitch_bend_minimum.into(),
+            ToneField::PitchBendMaximum => self.pitch_bend_maximum.into(),
+            ToneField::Adsr1 => self.adsr1,
+            ToneField::Adsr2 => self.adsr2,
+            ToneField::AttackRate => (self.adsr1 >> 8) & 0x7f,
+            ToneField::DecayRate => (self.adsr1 >> 4) & 0xf,
+            ToneField::SustainLevel => self.adsr1 & 0xf,
+            ToneField::SustainRate => (self.adsr2 >> 6) & 0x7f,
+            ToneField::ReleaseRate => self.adsr2 & 0x1f,
+        }
+    }
+
+    fn set(&mut self, field: ToneField, value: u16) {
+        match field {
+            ToneField::Priority => self.priority = value as u8,
+            ToneField::ReverbMode => self.reverb_mode = value as u8,
+            ToneField::Volume => self.volume = value as u8,
+            ToneField::Pan => self.pan = value as u8,
+            ToneField::UnityKey => self.unity_key = value as u8,
+            ToneField::PitchTune => self.pitch_tune = value as u8,
+            ToneField::KeyLow => self.key_low = value as u8,
+            ToneField::KeyHigh => self.key_high = value as u8,
+            ToneField::VibratoWidth => self.vibrato_width = value as u8,
+            ToneField::VibratoTime => self.vibrato_time = value as u8,
+            ToneField::PortWidth => self.port_width = value as u8,
+            ToneField::PortHold => self.port_hold = value as u8,
+            ToneField::PitchBendMinimum => self.pitch_bend_minimum = value as u8,
+            ToneField::PitchBendMaximum => self.pitch_bend_maximum = value as u8,
+            ToneField::Adsr1 => self.adsr1 = value,
+            ToneField::Adsr2 => self.adsr2 = value,
+            ToneField::AttackRate => self.adsr1 = (self.adsr1 & !(0x7f << 8)) | (value << 8),
+            ToneField::DecayRate => self.adsr1 = (self.adsr1 & !(0xf << 4)) | (value << 4),
+            ToneField::SustainLevel => self.adsr1 = (self.adsr1 & !0xf) | value,
+            ToneField::SustainRate => self.adsr2 = (self.adsr2 & !(0x7f << 6)) | (value << 6),
+            ToneField::ReleaseRate => self.adsr2 = (self.adsr2 & !0x1f) | value,
+        }
+    }
+}
+
+#[test]
+fn adsr_fields() {
+    let mut tone = Tone::parse(&mut [0; 32].iter());
+    tone.adsr1 = 0x80ff;
+    tone.set(ToneField::AttackRate, 0x12);
+    assert_eq!(tone.adsr1, 0x92ff);
+    assert_eq!(tone.get(ToneField::DecayRate), 0xf);
+    tone.set(ToneField::ReleaseRate, 0x1f);
+    assert_eq!(tone.get(ToneField::ReleaseRate), 0x1f);
+    assert_eq!(tone.to_bytes()[18], 0x1f);
+}
+
+#[test]
+fn out_of_range_edits() {
+    let set = Operation {
+        set: Some(300.0),
+        ..Default::default()
+    };
+    assert!(set.apply(10, 127).is_err());
+    let scale = Operation {
+        scale: Some(2.0),
+        ..Default::default()
+    };
+    assert_eq!(scale.apply(100, 127), Ok((127, true)));
+    assert_eq!(scale.apply(50, 127), Ok((100, false)));
+
+    let path = std::path::Path::new("patch.toml");
+    let text = "[[tone]]\nfield = \"volume\"\nscale = nan\n";
+    assert!(Patch::parse(text, path).is_err());
+    let text = "[[program]]\nfield = \"pan\"\noffset = -inf\n";
+    assert!(Patch::parse(text, path).is_err());
+    assert!(Patch::parse("[[program]]\nfield = \"pan\"\nscale = 0.5\n", path).is_ok());
+}
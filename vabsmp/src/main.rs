@@ -20,7 +20,11 @@ fn main() {
     let file = std::fs::read(&args.vab_path).unwrap();
     let mut file_iter = file.iter();
 
-    let vab_file = VabFile::parse(&mut file_iter, file.len());
+    let vab_file = VabFile::parse(&mut file_iter, file.len()).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(1);
+    });
+    vabheader::report_version(vab_file.header.version);
 
     let output_path = args.vab_path.with_extension("");
     std::fs::create_dir(&output_path).unwrap();
@@ -82,12 +86,9 @@ struct VabFile {
 }
 
 impl VabFile {
-    fn parse(bytes: &mut Iter<u8>, file_len: usize) -> Self {
+    fn parse(bytes: &mut Iter<u8>, file_len: usize) -> Result<Self, String> {
         let header = VabHeader::parse(bytes);
-        assert!(
-            (file_len as u32) >= header.total_size,
-            "File size mismatch!"
-        );
+        vabheader::check_total_size(header.total_size, file_len)?;
 
         let mut programs = Vec::with_capacity(header.programs_number as usize);
         let mut program_space = 0;
@@ -139,13 +140,13 @@ impl VabFile {
             })
             .0;
 
-        Self {
+        Ok(Self {
             header,
             programs,
             tones,
             vag_sizes,
             vag_ranges,
-        }
+        })
     }
 }
 
@@ -0,0 +1,238 @@
+use std::{fmt::Display, path::PathBuf};
+
+use clap::Parser;
+use spuadpcm::Decoder;
+
+const VAG_MAGIC_NUMBER: [u8; 4] = [0x56, 0x41, 0x47, 0x70];
+const ADS_MAGIC_NUMBER: [u8; 4] = [0x53, 0x53, 0x68, 0x64];
+
+#[derive(Parser)]
+#[clap(version)]
+struct Args {
+    /// The `vag` or `ads` files to analyze.
+    #[clap(required = true)]
+    inputs: Vec<PathBuf>,
+    /// Whether to list every frame that overflows.
+    #[clap(short, long)]
+    frames: bool,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    for input in &args.inputs {
+        let file = std::fs::read(input).unwrap();
+        println!("{}", input.to_string_lossy());
+
+        let channels = match split_channels(&file) {
+            Ok(channels) => channels,
+            Err(err) => {
+                eprintln!("{err}");
+                continue;
+            }
+        };
+        for (i, channel) in channels.iter().enumerate() {
+            if channels.len() > 1 {
+                println!("Channel {i}");
+            }
+            let analysis = Analysis::new(channel);
+            print!("{analysis}");
+            if args.frames {
+                for frame in &analysis.overflowing {
+                    println!("    {frame}");
+                }
+            }
+        }
+    }
+}
+
+/// The frames of a channel, along with their offset in the file.
+type Frames<'a> = Vec<(usize, &'a [u8])>;
+
+/// Splits the body of the file into frames for every channel.
+fn split_channels(file: &[u8]) -> Result<Vec<Frames<'_>>, String> {
+    match file.get(0..4) {
+        Some(magic) if magic == VAG_MAGIC_NUMBER => {
+            if file.len() < 48 {
+                return Err("The file is too small to hold a VAG header".to_owned());
+            }
+            // The long header has 16 more bytes of padding
+            let data_size = u32::from_be_bytes([file[12], file[13], file[14], file[15]]) as usize;
+            let header_size = if data_size + 64 == file.len() { 64 } else { 48 };
+            Ok(vec![file
+                .chunks_exact(16)
+                .enumerate()
+                .skip(header_size / 16)
+                .map(|(i, frame)| (i * 16, frame))
+                .collect()])
+        }
+        Some(magic) if magic == ADS_MAGIC_NUMBER => {
+            if file.len() < 0x28 {
+                return Err("The file is too small to hold an ADS header".to_owned());
+            }
+            let codec = load_bytes(&file[8..]);
+            if codec != 0x10 {
+                return Err(format!("Unsupported codec {codec:#x}"));
+            }
+            let channels = load_bytes(&file[0x10..]).max(1) as usize;
+            let interleave = load_bytes(&file[0x14..]) as usize;
+            let block_size = if channels == 1 || interleave == 0 {
+                file.len()
+            } else {
+                interleave
+            };
+
+            let mut frames = vec![vec![]; channels];
+            for (i, block) in file[0x28..].chunks(block_size).enumerate() {
+                let block_offset = 0x28 + i * block_size;
+                frames[i % channels].extend(
+                    block
+                        .chunks_exact(16)
+                        .enumerate()
+                        .map(|(j, frame)| (block_offset + j * 16, frame)),
+                );
+            }
+            Ok(frames)
+        }
+        _ => Err("Invalid magic number, expected a VAG or ADS file".to_owned()),
+    }
+}
+
+fn load_bytes(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+struct OverflowingFrame {
+    index: usize,
+    offset: usize,
+    filter: u8,
+    shift: u8,
+    clipped: usize,
+}
+
+impl Display for OverflowingFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Frame {} ({:#x}): filter {}, shift {}, {} clipped samples",
+            self.index, self.offset, self.filter, self.shift, self.clipped
+        )
+    }
+}
+
+struct Analysis {
+    samples: usize,
+    peak: i32,
+    clipped: usize,
+    dc_offset: f64,
+    /// The frame going the furthest out of range, or with the highest peak if none do.
+    worst_frame: Option<(usize, usize)>,
+    overflowing: Vec<OverflowingFrame>,
+}
+
+impl Analysis {
+    fn new(frames: &[(usize, &[u8])]) -> Self {
+        let mut decoder = Decoder::default();
+        let mut samples = 0;
+        let mut peak = 0;
+        let mut clipped = 0;
+        let mut sum = 0i64;
+        let mut worst_frame = None;
+        let mut worst_level = -1;
+        let mut overflowing = vec![];
+
+        for (i, (offset, frame)) in frames.iter().enumerate() {
+            let decoded = decoder.decode(frame);
+            let frame_clipped = decoded
+                .iter()
+                .filter(|x| **x > i16::MAX.into() || **x < i16::MIN.into())
+                .count();
+            let frame_level = decoded.iter().map(|x| x.abs()).max().unwrap_or_default();
+
+            samples += decoded.len();
+            peak = peak.max(frame_level.min(i16::MAX.into()));
+            clipped += frame_clipped;
+            sum += decoded
+                .iter()
+                .map(|x| i64::from((*x).clamp(i16::MIN.into(), i16::MAX.into())))
+                .sum::<i64>();
+            if frame_level > worst_level {
+                worst_level = frame_level;
+                worst_frame = Some((i, *offset));
+            }
+            if frame_clipped > 0 {
+                overflowing.push(OverflowingFrame {
+                    index: i,
+                    offset: *offset,
+                    filter: frame[0] >> 4,
+                    shift: frame[0] & 0xf,
+                    clipped: frame_clipped,
+                });
+            }
+
+            if frame[1] <= 7 && frame[1] & 1 != 0 {
+                break;
+            }
+        }
+
+        Self {
+            samples,
+            peak,
+            clipped,
+            dc_offset: if samples == 0 {
+                0.0
+            } else {
+                sum as f64 / samples as f64
+            },
+            worst_frame,
+            overflowing,
+        }
+    }
+}
+
+impl Display for Analysis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Samples: {}", self.samples)?;
+        writeln!(
+            f,
+            "Peak: {} ({:.2} dBFS)",
+            self.peak,
+            20.0 * (f64::from(self.peak) / 32768.0).log10()
+        )?;
+        writeln!(f, "Clipped samples: {}", self.clipped)?;
+        if let Some((index, offset)) = self.worst_frame {
+            writeln!(f, "Worst frame: {index} ({offset:#x})")?;
+        }
+        writeln!(
+            f,
+            "DC offset: {:.2} ({:.3}%)",
+            self.dc_offset,
+            self.dc_offset / 32768.0 * 100.0
+        )?;
+        writeln!(f, "Overflowing frames: {}", self.overflowing.len())
+    }
+}
+
+#[test]
+fn analysis() {
+    let mut file = [VAG_MAGIC_NUMBER, [0, 0, 0, 3], [0; 4], 32u32.to_be_bytes()].concat();
+    file.resize(64, 0);
+    // A frame of filter 1 with no shift, which overflows from its second sample on
+    file.extend([0x10, 0]);
+    file.extend([0x77; 14]);
+    file.extend([0, 1]);
+    file.extend([0; 14]);
+
+    let channels = split_channels(&file).unwrap();
+    assert_eq!(channels[0].len(), 2);
+    assert_eq!(channels[0][0].0, 64);
+    let analysis = Analysis::new(&channels[0]);
+    assert_eq!(analysis.samples, 56);
+    assert_eq!(analysis.peak, i16::MAX.into());
+    assert_eq!(analysis.clipped, 27);
+    assert_eq!(analysis.overflowing.len(), 1);
+    assert_eq!(analysis.worst_frame, Some((0, 64)));
+
+    assert!(split_channels(&file[..40]).is_err());
+    assert!(split_channels(&[*b"SShd", [0; 4]].concat()).is_err());
+}
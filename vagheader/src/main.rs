@@ -1,55 +1,200 @@
 use std::{fs::File, io::Write, num::NonZeroU32, path::PathBuf};
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 
 #[derive(Parser)]
 #[clap(version)]
 struct Args {
     input: PathBuf,
     sample_rate: NonZeroU32,
-    #[clap(long)]
+    /// Pad the 48 byte header with 16 more zero bytes.
+    #[clap(long, conflicts_with = "short")]
     long: bool,
-    #[clap(long)]
+    /// Deprecated, the 48 byte header is now the default.
+    #[clap(long, hide = true)]
     short: bool,
+    /// The name stored in the header, up to 16 bytes long.
+    #[clap(short, long, default_value = "")]
+    name: String,
+    #[clap(long, default_value = "3")]
+    header_version: HeaderVersion,
+    /// The number of interleaved channels, writing a `VAGi` file if there are several.
+    #[clap(short, long, default_value_t = 1)]
+    channels: u8,
+    /// The interleave in bytes of a `VAGi` file.
+    #[clap(short, long, default_value_t = 0x800)]
+    interleave: u32,
+    /// The size of the data of each channel, with the input cut or padded with zeros to it.
+    #[clap(short, long)]
+    data_size: Option<u32>,
     #[clap(short, long)]
     output: Option<PathBuf>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum HeaderVersion {
+    #[value(name = "2")]
+    V2,
+    #[value(name = "3")]
+    V3,
+    #[value(name = "0x20", alias = "32")]
+    V20,
+}
+
+impl HeaderVersion {
+    fn number(self) -> u32 {
+        match self {
+            Self::V2 => 2,
+            Self::V3 => 3,
+            Self::V20 => 0x20,
+        }
+    }
+}
+
 fn main() {
     let args = Args::parse();
 
+    if args.name.len() > 16 {
+        eprintln!("The name must be at most 16 bytes long");
+        std::process::exit(1);
+    }
+    if args.channels == 0 {
+        eprintln!("There must be at least 1 channel");
+        std::process::exit(1);
+    }
+    if args.channels > 1 && (args.interleave == 0 || !args.interleave.is_multiple_of(16)) {
+        eprintln!("The interleave must be a non-zero multiple of 16 bytes");
+        std::process::exit(1);
+    }
+    if args.short {
+        eprintln!("--short is deprecated, the header is 48 bytes long by default");
+    }
+
     let file = std::fs::read(&args.input).unwrap();
-    let file_len = file.len();
+    let vag = build(&args, file).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(1);
+    });
 
     let mut output = File::create(
         args.output
+            .clone()
             .unwrap_or_else(|| args.input.with_extension("vag")),
     )
     .unwrap();
+    output.write_all(&vag).unwrap();
+}
 
-    output
-        .write_all(
-            &[
-                [0x56, 0x41, 0x47, 0x70],
-                [0x0, 0x0, 0x0, 0x3],
-                [0; 4],
-                (file_len as u32).to_be_bytes(),
-                (args.sample_rate.get().to_be_bytes()),
-                [0; 4],
-                [0; 4],
-                [0; 4],
-                [0; 4],
-                [0; 4],
-                [0; 4],
-                [0; 4],
-            ]
-            .into_iter()
-            .flatten()
-            .collect::<Vec<_>>(),
-        )
-        .unwrap();
-    if !args.short {
-        output.write_all(&[0; 16]).unwrap();
+/// Puts the header in front of `file`, cut or padded to the data size.
+fn build(args: &Args, mut file: Vec<u8>) -> Result<Vec<u8>, String> {
+    let interleaved = args.channels > 1;
+    let channels = u32::from(args.channels);
+    let data_size = args.data_size.unwrap_or(file.len() as u32 / channels);
+    let body_size = data_size.checked_mul(channels).ok_or_else(|| {
+        format!("A data size of {data_size} bytes for {channels} channels doesn't fit in 4 GiB")
+    })? as usize;
+    if body_size < file.len() {
+        eprintln!(
+            "The last {} bytes of the input are past the data size and were left out",
+            file.len() - body_size
+        );
     }
-    output.write_all(&file).unwrap();
+    file.resize(body_size, 0);
+
+    let mut name = [0; 16];
+    name[..args.name.len()].copy_from_slice(args.name.as_bytes());
+    let (magic, interleave) = match interleaved {
+        true => (*b"VAGi", args.interleave.to_le_bytes()),
+        false => (*b"VAGp", [0; 4]),
+    };
+    let mut vag = [
+        magic,
+        args.header_version.number().to_be_bytes(),
+        interleave,
+        data_size.to_be_bytes(),
+        (args.sample_rate.get().to_be_bytes()),
+        [0; 4],
+        [0; 4],
+        [0, 0, if interleaved { args.channels } else { 0 }, 0],
+    ]
+    .into_iter()
+    .flatten()
+    .chain(name)
+    .collect::<Vec<_>>();
+    // The data of interleaved files starts at 0x800
+    if interleaved {
+        vag.resize(0x800, 0);
+    } else if args.long {
+        vag.resize(64, 0);
+    }
+    vag.extend(file);
+    Ok(vag)
+}
+
+#[test]
+fn header_layout() {
+    let vag = build(
+        &Args::parse_from([
+            "vagheader",
+            "in.bin",
+            "22050",
+            "-n",
+            "voice",
+            "--header-version",
+            "0x20",
+        ]),
+        vec![1; 40],
+    )
+    .unwrap();
+    assert_eq!(&vag[..4], b"VAGp");
+    assert_eq!(vag[4..8], 0x20_u32.to_be_bytes());
+    assert_eq!(vag[8..12], [0; 4]);
+    assert_eq!(vag[12..16], 40_u32.to_be_bytes());
+    assert_eq!(vag[16..20], 22050_u32.to_be_bytes());
+    assert_eq!(&vag[0x20..0x30], b"voice\0\0\0\0\0\0\0\0\0\0\0");
+    assert_eq!(vag.len(), 48 + 40);
+
+    let vag = build(
+        &Args::parse_from(["vagheader", "in.bin", "22050", "--long", "-d", "48"]),
+        vec![1; 40],
+    )
+    .unwrap();
+    assert_eq!(vag[12..16], 48_u32.to_be_bytes());
+    assert_eq!(vag[48..64], [0; 16]);
+    assert_eq!(vag[64..], [[1; 40].as_slice(), &[0; 8]].concat());
+
+    let vag = build(
+        &Args::parse_from([
+            "vagheader",
+            "in.bin",
+            "44100",
+            "-c",
+            "2",
+            "-i",
+            "16",
+            "-d",
+            "16",
+        ]),
+        vec![1; 40],
+    )
+    .unwrap();
+    assert_eq!(&vag[..4], b"VAGi");
+    assert_eq!(vag[4..8], 3_u32.to_be_bytes());
+    assert_eq!(vag[8..12], 0x10_u32.to_le_bytes());
+    assert_eq!(vag[12..16], 16_u32.to_be_bytes());
+    assert_eq!(vag[0x1E], 2);
+    assert_eq!(vag.len(), 0x800 + 32);
+    assert_eq!(vag[0x800..], [1; 32]);
+
+    let args = Args::parse_from([
+        "vagheader",
+        "in.bin",
+        "44100",
+        "-c",
+        "2",
+        "-d",
+        "4294967295",
+    ]);
+    assert!(build(&args, vec![]).is_err());
+    assert!(Args::try_parse_from(["vagheader", "in.bin", "44100", "--short"]).is_ok());
 }
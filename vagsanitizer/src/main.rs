@@ -1,26 +1,242 @@
-use std::{fs::File, io::Write, path::PathBuf};
+use std::{fmt::Display, fs::File, io::Write, path::PathBuf};
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+
+const VAG_MAGIC_NUMBER: [u8; 4] = [0x56, 0x41, 0x47, 0x70];
+const VAB_MAGIC_NUMBER: [u8; 4] = [0x70, 0x42, 0x41, 0x56];
+const ADS_MAGIC_NUMBER: [u8; 4] = [0x53, 0x53, 0x68, 0x64];
+const SMP_MAGIC_NUMBER: [u8; 4] = [0x50, 0x4d, 0x53, 0x61];
 
 #[derive(Parser)]
 struct Args {
-    /// The `vag` file to read from.
+    /// The `vag`, `vab`, `vb`, `ads` or `smp` file to read from.
     input: PathBuf,
     /// The output directory
     #[clap(short, long)]
     output: Option<PathBuf>,
+    /// The kinds of defects to fix.
+    #[clap(short, long, value_delimiter = ',', default_value = "shift-filter")]
+    fix: Vec<Fix>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Fix {
+    /// Replaces invalid shift/filter bytes with the one of the previous frame.
+    ShiftFilter,
+    /// Clears unknown flag values.
+    Flags,
+    /// Removes the frames after the end flag, only for `vag` files.
+    Trailing,
+    /// Sets the end flag on the last frame.
+    End,
+    /// Sets the loop end flag on the last frame of an unclosed loop.
+    Loop,
+    /// Rewrites the data size in the header, only for `vag` files.
+    Size,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Container {
+    /// A `vag` file, with either the short or the long header.
+    Vag {
+        header_size: usize,
+    },
+    Vab,
+    Vb,
+    Ads,
+    /// A Soul Reaver `smp` file, with or without its magic number.
+    Smp {
+        header_size: usize,
+    },
+}
+
+impl Container {
+    fn detect(bytes: &[u8], input: &std::path::Path) -> Option<Self> {
+        let extension = input
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        match bytes.get(0..4)? {
+            magic if magic == VAG_MAGIC_NUMBER => {
+                let data_size = bytes.get(12..16).map_or(0, |size| {
+                    u32::from_be_bytes([size[0], size[1], size[2], size[3]])
+                });
+                let header_size = if data_size as usize + 64 == bytes.len() {
+                    64
+                } else {
+                    48
+                };
+                Some(Self::Vag { header_size })
+            }
+            magic if magic == VAB_MAGIC_NUMBER => Some(Self::Vab),
+            magic if magic == ADS_MAGIC_NUMBER => Some(Self::Ads),
+            magic if magic == SMP_MAGIC_NUMBER => Some(Self::Smp { header_size: 8 }),
+            _ => match extension.as_deref() {
+                Some("vb") => Some(Self::Vb),
+                Some("smp") => Some(Self::Smp { header_size: 4 }),
+                _ => None,
+            },
+        }
+    }
+
+    /// Finds the offset of every frame of every sample in the container.
+    fn samples(self, bytes: &[u8]) -> Result<Vec<Vec<usize>>, String> {
+        match self {
+            Self::Vag { header_size } => {
+                if bytes.len() < header_size {
+                    return Err("The file is too small to hold a VAG header".to_owned());
+                }
+                Ok(vec![frame_offsets(header_size..bytes.len())])
+            }
+            Self::Vab => {
+                let header = bytes
+                    .get(..0x20)
+                    .ok_or("The file is too small to hold a VAB header")?;
+                let programs_number = u16::from_le_bytes([header[0x12], header[0x13]]) as usize;
+                let vags_number = u16::from_le_bytes([header[0x16], header[0x17]]) as usize;
+                if vags_number > 255 {
+                    return Err(format!(
+                        "The header has {vags_number} VAGs, but there is only room for 255"
+                    ));
+                }
+                // The first entry of the table is always 0
+                let vag_table = 0x20 + 128 * 16 + programs_number * 512;
+                let sizes = bytes
+                    .get(vag_table + 2..vag_table + 512)
+                    .ok_or("The file is too small to hold its VAG table")?;
+                let mut cursor = vag_table + 512;
+                Ok(sizes
+                    .chunks_exact(2)
+                    .take(vags_number)
+                    .map(|size| {
+                        let size = u16::from_le_bytes([size[0], size[1]]) as usize * 8;
+                        let range = cursor.min(bytes.len())..(cursor + size).min(bytes.len());
+                        cursor += size;
+                        frame_offsets(range)
+                    })
+                    .collect())
+            }
+            Self::Vb => Ok(split_at_ends(bytes, 0)),
+            Self::Smp { header_size } => Ok(split_at_ends(bytes, header_size)),
+            Self::Ads => {
+                if bytes.len() < 0x28 {
+                    return Err("The file is too small to hold an ADS header".to_owned());
+                }
+                let codec = load_bytes(&bytes[8..]);
+                if codec != 0x10 {
+                    return Err(format!("Unsupported codec {codec:#x}"));
+                }
+                let channels = load_bytes(&bytes[0x10..]).max(1) as usize;
+                let interleave = load_bytes(&bytes[0x14..]) as usize;
+                if channels == 1 || interleave == 0 {
+                    return Ok(vec![frame_offsets(0x28..bytes.len())]);
+                }
+
+                let mut samples = vec![vec![]; channels];
+                for (i, start) in (0x28..bytes.len()).step_by(interleave).enumerate() {
+                    samples[i % channels]
+                        .extend(frame_offsets(start..(start + interleave).min(bytes.len())));
+                }
+                Ok(samples)
+            }
+        }
+    }
+}
+
+impl Display for Container {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Vag { header_size } => write!(f, "VAG ({header_size}-byte header)"),
+            Self::Vab => write!(f, "VAB"),
+            Self::Vb => write!(f, "VB"),
+            Self::Ads => write!(f, "ADS"),
+            Self::Smp { header_size } => write!(f, "SMP ({header_size}-byte header)"),
+        }
+    }
 }
 
 fn main() {
     let args = Args::parse();
 
-    let mut vag_bytes = std::fs::read(&args.input).unwrap();
-    assert_eq!(
-        vag_bytes[0..4],
-        [0x56, 0x41, 0x47, 0x70],
-        "invalid magic number"
-    );
-    let changed = sanitized(&mut vag_bytes);
+    let mut bytes = std::fs::read(&args.input).unwrap();
+    let container = Container::detect(&bytes, &args.input).unwrap_or_else(|| {
+        eprintln!("Unknown container, expected a vag, vab, vb, ads or smp file");
+        std::process::exit(1);
+    });
+    let samples = container.samples(&bytes).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(1);
+    });
+    println!("Container: {container}");
+    if container == Container::Vab {
+        vabheader::report_version(load_bytes(&bytes[4..]));
+    }
+    println!("Samples found: {}", samples.len());
+
+    if !matches!(container, Container::Vag { .. })
+        && (args.fix.contains(&Fix::Trailing) || args.fix.contains(&Fix::Size))
+    {
+        eprintln!("The trailing and size fixes are only available for vag files, ignoring them");
+    }
+
+    let mut defects_found = 0;
+    let mut changed = 0;
+    for (i, offsets) in samples.iter().enumerate() {
+        let mut body = offsets
+            .iter()
+            .flat_map(|offset| &bytes[*offset..offset + 16])
+            .copied()
+            .collect::<Vec<_>>();
+
+        let mut defects = check(&body);
+        if let Container::Vag { header_size } = container {
+            let found = u32::from_be_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]);
+            let expected = (bytes.len() - header_size) as u32;
+            if found != expected {
+                defects.push(Defect::Size { found, expected });
+            }
+        }
+        for defect in &defects {
+            let sample = match container {
+                Container::Vag { .. } => String::new(),
+                _ => format!("Sample {i}: "),
+            };
+            match defect.frame() {
+                Some(frame) => println!("{sample}Frame {frame} ({:#x}): {defect}", offsets[frame]),
+                None => println!("{sample}{defect}"),
+            }
+        }
+        defects_found += defects.len();
+
+        if args.fix.contains(&Fix::ShiftFilter) {
+            changed += sanitized(&mut body);
+        }
+        if args.fix.contains(&Fix::Flags) {
+            changed += cleared_flags(&mut body);
+        }
+        if args.fix.contains(&Fix::End) {
+            changed += ended(&mut body);
+        }
+        if args.fix.contains(&Fix::Loop) {
+            changed += closed_loop(&mut body);
+        }
+        for (offset, frame) in offsets.iter().zip(body.chunks_exact(16)) {
+            bytes[*offset..offset + 16].copy_from_slice(frame);
+        }
+
+        if let Container::Vag { header_size } = container {
+            if args.fix.contains(&Fix::Trailing) {
+                let removed = truncated(&mut body);
+                if removed != 0 {
+                    bytes.truncate(header_size + body.len());
+                }
+                changed += removed;
+            }
+            if args.fix.contains(&Fix::Size) {
+                changed += resized(&mut bytes, header_size);
+            }
+        }
+    }
+    println!("Defects found: {defects_found}");
 
     if changed != 0 {
         println!("{changed} bad chunks fixed!");
@@ -33,17 +249,139 @@ fn main() {
             .into()
         }))
         .unwrap();
-        output.write_all(&vag_bytes).unwrap();
+        output.write_all(&bytes).unwrap();
     } else {
         println!("No bad chunks were found!");
     }
 }
 
-fn sanitized(bytes: &mut [u8]) -> usize {
+fn load_bytes(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn frame_offsets(range: std::ops::Range<usize>) -> Vec<usize> {
+    let end = range.end;
+    range.step_by(16).take_while(|x| x + 16 <= end).collect()
+}
+
+/// Splits a headerless body into samples, each ending on a frame with the end flag.
+fn split_at_ends(bytes: &[u8], start: usize) -> Vec<Vec<usize>> {
+    let mut samples = vec![];
+    let mut current = vec![];
+    for offset in frame_offsets(start..bytes.len()) {
+        current.push(offset);
+        if is_end(bytes[offset + 1]) {
+            samples.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        samples.push(current);
+    }
+    samples
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Defect {
+    /// The filter index is above 4.
+    Filter { frame: usize, filter: u8 },
+    /// The shift is above 12.
+    Shift { frame: usize, shift: u8 },
+    /// The flag byte isn't a combination of the end, repeat and start bits.
+    Flag { frame: usize, flag: u8 },
+    /// The last frame has no end flag.
+    MissingEnd,
+    /// A frame starts a loop that never gets to a loop end.
+    UnclosedLoop { frame: usize },
+    /// A frame comes after the end flag.
+    AfterEnd { frame: usize },
+    /// The data size in the header differs from the size of the body.
+    Size { found: u32, expected: u32 },
+}
+
+impl Defect {
+    fn frame(&self) -> Option<usize> {
+        match self {
+            Self::Filter { frame, .. }
+            | Self::Shift { frame, .. }
+            | Self::Flag { frame, .. }
+            | Self::UnclosedLoop { frame }
+            | Self::AfterEnd { frame } => Some(*frame),
+            Self::MissingEnd | Self::Size { .. } => None,
+        }
+    }
+}
+
+impl Display for Defect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Filter { filter, .. } => write!(f, "filter {filter} above 4"),
+            Self::Shift { shift, .. } => write!(f, "shift {shift} above 12"),
+            Self::Flag { flag, .. } => write!(f, "unknown flag {flag:#x}"),
+            Self::MissingEnd => write!(f, "last frame has no end flag"),
+            Self::UnclosedLoop { .. } => write!(f, "loop start without loop end"),
+            Self::AfterEnd { .. } => write!(f, "data after the end flag"),
+            Self::Size { found, expected } => write!(
+                f,
+                "header data size is {found} bytes, but the body is {expected} bytes"
+            ),
+        }
+    }
+}
+
+/// Whether a flag byte marks the end of the sample.
+fn is_end(flag: u8) -> bool {
+    flag <= 7 && flag & 1 != 0
+}
+
+fn check(body: &[u8]) -> Vec<Defect> {
+    let mut defects = vec![];
+    let mut loop_start = None;
+    let mut end = None;
+
+    for (i, frame) in body.chunks_exact(16).enumerate() {
+        if end.is_some() {
+            defects.push(Defect::AfterEnd { frame: i });
+            continue;
+        }
+        let filter = frame[0] >> 4;
+        let shift = frame[0] & 0xf;
+        if filter > 4 {
+            defects.push(Defect::Filter { frame: i, filter });
+        }
+        if shift > 12 {
+            defects.push(Defect::Shift { frame: i, shift });
+        }
+        let flag = frame[1];
+        if flag > 7 {
+            defects.push(Defect::Flag { frame: i, flag });
+            continue;
+        }
+        if flag & 4 != 0 {
+            loop_start = Some(i);
+        }
+        if flag & 1 != 0 {
+            if flag & 2 != 0 {
+                loop_start = None;
+            }
+            end = Some(i);
+        }
+    }
+
+    if let Some(frame) = loop_start {
+        defects.push(Defect::UnclosedLoop { frame });
+    }
+    if end.is_none() {
+        defects.push(Defect::MissingEnd);
+    }
+
+    defects
+}
+
+fn sanitized(body: &mut [u8]) -> usize {
     let mut last_valid = 0;
     let mut changed = 0;
-    for line in bytes.chunks_mut(16).skip(48 / 16) {
-        if line[0] == 0xff {
+    for line in body.chunks_exact_mut(16) {
+        if line[0] >> 4 > 4 || line[0] & 0xf > 12 {
             line[0] = last_valid;
             changed += 1;
         } else {
@@ -52,3 +390,127 @@ fn sanitized(bytes: &mut [u8]) -> usize {
     }
     changed
 }
+
+fn cleared_flags(body: &mut [u8]) -> usize {
+    let mut changed = 0;
+    for line in body.chunks_exact_mut(16) {
+        if line[1] > 7 {
+            line[1] = 0;
+            changed += 1;
+        }
+    }
+    changed
+}
+
+fn truncated(body: &mut Vec<u8>) -> usize {
+    let frame_count = body.len() / 16;
+    match body.chunks_exact(16).position(|line| is_end(line[1])) {
+        Some(end) if end + 1 < frame_count => {
+            body.truncate((end + 1) * 16);
+            frame_count - end - 1
+        }
+        _ => 0,
+    }
+}
+
+fn ended(body: &mut [u8]) -> usize {
+    if body.chunks_exact(16).any(|line| is_end(line[1])) {
+        return 0;
+    }
+    match body.chunks_exact_mut(16).last() {
+        Some(line) => {
+            line[1] |= 1;
+            1
+        }
+        None => 0,
+    }
+}
+
+fn closed_loop(body: &mut [u8]) -> usize {
+    if !check(body)
+        .iter()
+        .any(|defect| matches!(defect, Defect::UnclosedLoop { .. }))
+    {
+        return 0;
+    }
+    let end = body
+        .chunks_exact(16)
+        .position(|line| is_end(line[1]))
+        .unwrap_or_else(|| (body.len() / 16).saturating_sub(1));
+    match body.chunks_exact_mut(16).nth(end) {
+        Some(line) => {
+            line[1] = (line[1] & 4) | 3;
+            1
+        }
+        None => 0,
+    }
+}
+
+fn resized(bytes: &mut [u8], header_size: usize) -> usize {
+    let expected = bytes.len().saturating_sub(header_size) as u32;
+    if bytes[12..16] == expected.to_be_bytes() {
+        return 0;
+    }
+    bytes[12..16].copy_from_slice(&expected.to_be_bytes());
+    1
+}
+
+#[test]
+fn defects() {
+    let mut body = vec![];
+    for frame in [[0x00, 0x00], [0xff, 0x04], [0x1d, 0x09], [0x12, 0x01]] {
+        body.extend(frame);
+        body.extend([0; 14]);
+    }
+    assert_eq!(
+        check(&body),
+        vec![
+            Defect::Filter {
+                frame: 1,
+                filter: 15
+            },
+            Defect::Shift {
+                frame: 1,
+                shift: 15
+            },
+            Defect::Shift {
+                frame: 2,
+                shift: 13
+            },
+            Defect::Flag { frame: 2, flag: 9 },
+            Defect::UnclosedLoop { frame: 1 },
+        ]
+    );
+    assert_eq!(closed_loop(&mut body), 1);
+    assert_eq!(body[3 * 16 + 1], 3);
+    assert_eq!(split_at_ends(&[body.clone(), body].concat(), 0).len(), 2);
+}
+
+#[test]
+fn truncated_containers() {
+    let mut vab = [VAB_MAGIC_NUMBER, [0; 4]].concat();
+    vab.resize(0x20 + 128 * 16 + 512 + 4, 0);
+    vab[0x12] = 1;
+    vab[0x16] = 1;
+    vab[0x20 + 128 * 16 + 512 + 2] = 2;
+    let path = std::path::Path::new("test.vab");
+    assert!(Container::Vab.samples(&vab[..0x10]).is_err());
+    assert!(Container::Vab.samples(&vab).is_err());
+    vab.resize(0x20 + 128 * 16 + 1024 + 16, 0);
+    assert_eq!(Container::detect(&vab, path), Some(Container::Vab));
+    assert_eq!(Container::Vab.samples(&vab), Ok(vec![vec![0x820 + 1024]]));
+    vab[0x16] = 0;
+    vab[0x17] = 1;
+    assert!(Container::Vab.samples(&vab).is_err());
+
+    let ads = [ADS_MAGIC_NUMBER, [0x20, 0, 0, 0], [0x10, 0, 0, 0]].concat();
+    assert_eq!(Container::detect(&ads, path), Some(Container::Ads));
+    assert!(Container::Ads.samples(&ads).is_err());
+    let vag = [VAG_MAGIC_NUMBER, [0; 4]].concat();
+    let header_size = 48;
+    assert_eq!(
+        Container::detect(&vag, path),
+        Some(Container::Vag { header_size })
+    );
+    assert!(Container::Vag { header_size }.samples(&vag).is_err());
+}